@@ -0,0 +1,315 @@
+use crate::diagnostic::Diagnostic;
+use crate::toc::TocEntry;
+
+/// Struct describing the byte range of a node in the markdown text.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Span {
+    pub begin: usize,
+    pub end: usize,
+}
+
+impl Span {
+    pub fn new(begin: usize, end: usize) -> Span {
+        Span {
+            begin,
+            end,
+        }
+    }
+}
+
+/// Enum describing the text alignment of a table column.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Alignment {
+    Left,
+    Right,
+    Center,
+}
+
+/// Enum describing the kind of an emphasis node.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Emphasis {
+    Italic,
+    Bold,
+    Strike,
+    Underline,
+}
+
+/// Enum describing how a piece of a codeblock was highlighted.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Highlight {
+    Text,
+    String,
+    Char,
+    Digit,
+    Keyword1,
+    Keyword2,
+    Keyword3,
+    Symbol,
+    Function,
+    Comment,
+    Escape,
+}
+
+/// Enum describing all the elements of the document tree.
+///
+/// Every node stores the `Span` of the markdown it was created from.
+/// Nodes that can contain other nodes store them in `children`.
+#[derive(Debug, PartialEq, Clone)]
+pub enum Node {
+    Heading {
+        level: usize,
+        /// The `{#custom-id}` given in the markdown or a slug of the heading's text,
+        /// unique within the document.
+        id: String,
+        children: Vec<Node>,
+        span: Span,
+    },
+    Paragraph {
+        children: Vec<Node>,
+        span: Span,
+    },
+    Blockquote {
+        children: Vec<Node>,
+        span: Span,
+    },
+    /// A blockquote starting with a line like `[!NOTE]`, see `AlertKind`.
+    Alert {
+        /// The name of the alert kind in lowercase.
+        kind: String,
+        title: String,
+        children: Vec<Node>,
+        span: Span,
+    },
+    /// `children` only contains `ListItem` nodes.
+    List {
+        ordered: bool,
+        /// The number of the first item, always 1 for unordered lists.
+        start: usize,
+        /// A list is tight if no blank line separates its items or the blocks inside of them.
+        /// The paragraphs of a tight list are rendered without `<p>`.
+        tight: bool,
+        children: Vec<Node>,
+        span: Span,
+    },
+    /// `children` are the blocks inside the item, nested lists included.
+    ListItem {
+        children: Vec<Node>,
+        span: Span,
+    },
+    /// `children` only contains `Row` nodes.
+    Table {
+        children: Vec<Node>,
+        span: Span,
+    },
+    /// `children` only contains `Cell` nodes.
+    Row {
+        header: bool,
+        children: Vec<Node>,
+        span: Span,
+    },
+    Cell {
+        header: bool,
+        alignment: Alignment,
+        children: Vec<Node>,
+        span: Span,
+    },
+    /// `children` only contains `CodeSpan` nodes.
+    CodeBlock {
+        language: String,
+        /// The `key=value` attributes of the info string following the language, in order.
+        attributes: Vec<(String, String)>,
+        /// Whether the lines are numbered, enabled by a `linenos` attribute.
+        line_numbers: bool,
+        /// The number of the first line, given by a `linenostart` attribute.
+        first_line: usize,
+        /// Inclusive ranges of the line numbers to emphasize, given like `{3,5-7}` or `hl_lines="3 5-7"`.
+        highlighted_lines: Vec<(usize, usize)>,
+        children: Vec<Node>,
+        span: Span,
+    },
+    CodeSpan {
+        highlight: Highlight,
+        text: String,
+        span: Span,
+    },
+    IndentBlock {
+        text: String,
+        span: Span,
+    },
+    HorizontalRule {
+        span: Span,
+    },
+    /// A paragraph containing only `[TOC]`, `entries` are the headings of the whole document.
+    Toc {
+        entries: Vec<TocEntry>,
+        span: Span,
+    },
+    /// A `[^label]: text` definition, `children` are the blocks of the footnote.
+    /// `number` is given by the order of the first references to the footnotes.
+    FootnoteDefinition {
+        label: String,
+        number: usize,
+        /// How many times the footnote is referenced.
+        references: usize,
+        children: Vec<Node>,
+        span: Span,
+    },
+    /// The referenced footnotes, appended at the end of the document.
+    /// `children` only contains `FootnoteDefinition` nodes, ordered by their number.
+    Footnotes {
+        children: Vec<Node>,
+        span: Span,
+    },
+    Emphasis {
+        kind: Emphasis,
+        children: Vec<Node>,
+        span: Span,
+    },
+    Code {
+        text: String,
+        span: Span,
+    },
+    Link {
+        href: String,
+        /// The title given after the url, `[text](url "title")`.
+        title: Option<String>,
+        children: Vec<Node>,
+        span: Span,
+    },
+    Image {
+        alt: String,
+        src: String,
+        title: Option<String>,
+        span: Span,
+    },
+    /// A `[^label]` reference, `index` counts the references to the same footnote starting at 1.
+    FootnoteReference {
+        label: String,
+        number: usize,
+        index: usize,
+        span: Span,
+    },
+    Checkbox {
+        checked: bool,
+        span: Span,
+    },
+    Html {
+        html: String,
+        span: Span,
+    },
+    Text {
+        text: String,
+        span: Span,
+    },
+    /// A newline inside a paragraph, the lines are joined into one paragraph.
+    SoftBreak {
+        span: Span,
+    },
+    /// A line ending in two or more spaces or a backslash.
+    LineBreak {
+        span: Span,
+    },
+    Error {
+        text: String,
+        span: Span,
+    },
+}
+
+impl Node {
+    /// Returns the `Span` of the node.
+    pub fn span(&self) -> Span {
+        match self {
+            Node::Heading { span, .. }|
+            Node::Paragraph { span, .. }|
+            Node::Blockquote { span, .. }|
+            Node::Alert { span, .. }|
+            Node::List { span, .. }|
+            Node::ListItem { span, .. }|
+            Node::Table { span, .. }|
+            Node::Row { span, .. }|
+            Node::Cell { span, .. }|
+            Node::CodeBlock { span, .. }|
+            Node::CodeSpan { span, .. }|
+            Node::IndentBlock { span, .. }|
+            Node::HorizontalRule { span }|
+            Node::Toc { span, .. }|
+            Node::FootnoteDefinition { span, .. }|
+            Node::Footnotes { span, .. }|
+            Node::Emphasis { span, .. }|
+            Node::Code { span, .. }|
+            Node::Link { span, .. }|
+            Node::Image { span, .. }|
+            Node::FootnoteReference { span, .. }|
+            Node::Checkbox { span, .. }|
+            Node::Html { span, .. }|
+            Node::Text { span, .. }|
+            Node::SoftBreak { span }|
+            Node::LineBreak { span }|
+            Node::Error { span, .. } => *span,
+        }
+    }
+
+    /// Returns the child nodes, leaf nodes return an empty slice.
+    pub fn children(&self) -> &[Node] {
+        match self {
+            Node::Heading { children, .. }|
+            Node::Paragraph { children, .. }|
+            Node::Blockquote { children, .. }|
+            Node::Alert { children, .. }|
+            Node::List { children, .. }|
+            Node::ListItem { children, .. }|
+            Node::Table { children, .. }|
+            Node::Row { children, .. }|
+            Node::Cell { children, .. }|
+            Node::CodeBlock { children, .. }|
+            Node::FootnoteDefinition { children, .. }|
+            Node::Footnotes { children, .. }|
+            Node::Emphasis { children, .. }|
+            Node::Link { children, .. } => children,
+            _ => &[],
+        }
+    }
+
+    /// Returns the text of the node and its children without any markup.
+    /// Images contribute their alt text and line breaks a space.
+    pub fn plain_text(&self) -> String {
+        match self {
+            Node::Text { text, .. }|
+            Node::Code { text, .. }|
+            Node::CodeSpan { text, .. }|
+            Node::IndentBlock { text, .. } => text.clone(),
+            Node::Image { alt, .. } => alt.clone(),
+            Node::SoftBreak { .. }|Node::LineBreak { .. } => " ".to_string(),
+            _ => self.children().iter().map(|c| c.plain_text()).collect(),
+        }
+    }
+
+    /// Returns the child nodes for changing them, leaf nodes return `None`.
+    pub fn children_mut(&mut self) -> Option<&mut Vec<Node>> {
+        match self {
+            Node::Heading { children, .. }|
+            Node::Paragraph { children, .. }|
+            Node::Blockquote { children, .. }|
+            Node::Alert { children, .. }|
+            Node::List { children, .. }|
+            Node::ListItem { children, .. }|
+            Node::Table { children, .. }|
+            Node::Row { children, .. }|
+            Node::Cell { children, .. }|
+            Node::CodeBlock { children, .. }|
+            Node::FootnoteDefinition { children, .. }|
+            Node::Footnotes { children, .. }|
+            Node::Emphasis { children, .. }|
+            Node::Link { children, .. } => Some(children),
+            _ => None,
+        }
+    }
+}
+
+/// Struct that represents a parsed markdown document.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Document {
+    pub children: Vec<Node>,
+    /// Problems found while parsing, like links to undefined references.
+    pub diagnostics: Vec<Diagnostic>,
+}
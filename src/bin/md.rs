@@ -8,17 +8,23 @@
 //!
 //! `css` - The path to the css file. This argument is optional. 
 //! For making your own style take a look at the included css file.
+//!
+//! `md --toc <input>` prints the table of contents of the markdown file instead.
 
-use std::io;
 use std::env;
+use std::fs;
 
 use markdown;
 
 /// Parses args and calls the lib to generate html.
-fn main() -> Result<(), io::Error> {
+fn main() -> Result<(), markdown::Error> {
     let args: Vec<String> = env::args().collect();
 
-    if args.len() == 3 {
+    if args.len() == 3 && args[1] == "--toc" {
+        // Print the headings as a nested markdown list.
+        let text = fs::read_to_string(&args[2])?;
+        print_toc(&markdown::toc(&text)?, 0);
+    } else if args.len() == 3 {
         // Convert Markdown to HTML without css.
         let input = args.get(1).unwrap().as_str();
         let output = args.get(2).unwrap().as_str();
@@ -32,8 +38,17 @@ fn main() -> Result<(), io::Error> {
     }  else {
         println!("ERROR: Wrong number of arguments!");
         println!("\t$ md <input> <output> <css>");
+        println!("\t$ md --toc <input>");
         println!("NOTE: The <css> argument is optional.");
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+/// Prints every entry as a list item linking to the heading, indented by its depth.
+fn print_toc(entries: &[markdown::TocEntry], depth: usize) {
+    for e in entries {
+        println!("{}- [{}](#{})", "  ".repeat(depth), e.text, e.id);
+        print_toc(&e.children, depth + 1);
+    }
+}
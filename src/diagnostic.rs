@@ -0,0 +1,18 @@
+use crate::ast::Span;
+
+/// Struct describing a problem with the markdown that didn't stop it from being converted.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Diagnostic {
+    pub message: String,
+    /// The markdown the diagnostic is about.
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn new(message: String, span: Span) -> Diagnostic {
+        Diagnostic {
+            message,
+            span,
+        }
+    }
+}
@@ -0,0 +1,48 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+use crate::token::TokenType;
+
+/// Enum describing all the errors returned by the library.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading the markdown or css file, or writing the html file failed.
+    Io(io::Error),
+    /// A syntax file exists but couldn't be read or is missing required values.
+    Syntax {
+        path: String,
+        message: String,
+    },
+    /// The parser encountered a token it didn't expect at this position.
+    /// `index` is the position of the token in the token stream.
+    MalformedTokens {
+        index: usize,
+        expected: TokenType,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "io error: {}", e),
+            Error::Syntax { path, message } => write!(f, "invalid syntax file '{}': {}", path, message),
+            Error::MalformedTokens { index, expected } => write!(f, "malformed token stream: expected {:?} at token {}", expected, index),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
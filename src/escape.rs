@@ -0,0 +1,87 @@
+/// Escapes text for use between html tags.
+///
+/// Character references like `&amp;` or `&#60;` that are already in
+/// the text are kept as they are, every other `&` is escaped.
+pub fn escape_text(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for (i, c) in text.char_indices() {
+        match c {
+            '&' if is_reference(&text[i..]) => escaped.push('&'),
+            _ => push_escaped(&mut escaped, c, false),
+        }
+    }
+
+    escaped
+}
+
+/// Escapes text for use inside of a double or single quoted attribute value.
+pub fn escape_attribute(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        push_escaped(&mut escaped, c, true);
+    }
+
+    escaped
+}
+
+/// Escapes code, unlike `escape_text` every `&` is escaped
+/// so the code is displayed exactly as it was written.
+pub fn escape_code(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        push_escaped(&mut escaped, c, false);
+    }
+
+    escaped
+}
+
+fn push_escaped(escaped: &mut String, c: char, quotes: bool) {
+    match c {
+        '&' => escaped.push_str("&amp;"),
+        '<' => escaped.push_str("&lt;"),
+        '>' => escaped.push_str("&gt;"),
+        '"' if quotes => escaped.push_str("&quot;"),
+        '\'' if quotes => escaped.push_str("&#39;"),
+        _ => escaped.push(c),
+    }
+}
+
+/// Checks whether `text` starts with a named, decimal or hexadecimal character reference.
+fn is_reference(text: &str) -> bool {
+    let body = match text[1..].find(';') {
+        Some(end) => &text[1..end + 1],
+        None => return false,
+    };
+    if let Some(number) = body.strip_prefix('#') {
+        match number.strip_prefix('x').or_else(|| number.strip_prefix('X')) {
+            Some(hex) => !hex.is_empty() && hex.len() <= 6 && hex.chars().all(|c| c.is_ascii_hexdigit()),
+            None => !number.is_empty() && number.len() <= 7 && number.chars().all(|c| c.is_ascii_digit()),
+        }
+    } else {
+        body.len() <= 32
+            && body.starts_with(|c: char| c.is_ascii_alphabetic())
+            && body.chars().all(|c| c.is_ascii_alphanumeric())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text() {
+        assert_eq!(escape_text("a < b && c > d"), "a &lt; b &amp;&amp; c &gt; d");
+        assert_eq!(escape_text("&copy; &#169; &#xA9; &nope &;"), "&copy; &#169; &#xA9; &amp;nope &amp;;");
+        assert_eq!(escape_text("\"quoted\" 'ä'"), "\"quoted\" 'ä'");
+    }
+
+    #[test]
+    fn attribute() {
+        assert_eq!(escape_attribute("a\" onclick=\"x'&<>"), "a&quot; onclick=&quot;x&#39;&amp;&lt;&gt;");
+    }
+
+    #[test]
+    fn code() {
+        assert_eq!(escape_code("Vec<T> &amp;"), "Vec&lt;T&gt; &amp;amp;");
+    }
+}
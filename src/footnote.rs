@@ -0,0 +1,201 @@
+use std::collections::HashMap;
+use std::mem;
+
+use crate::ast::Node;
+use crate::ast::Span;
+use crate::diagnostic::Diagnostic;
+use crate::reference::normalize;
+
+/// Numbers the footnotes and moves their definitions into a `Footnotes` node at the end of `nodes`.
+///
+/// Footnotes are numbered in the order of their first reference, references inside of a
+/// footnote count as well. References to undefined footnotes are turned back into text.
+/// Returns a `Diagnostic` for every undefined, duplicate or unused footnote.
+pub fn collect(nodes: &mut Vec<Node>) -> Vec<Diagnostic> {
+    let mut definitions: Vec<Node> = Vec::new();
+    take_definitions(nodes, &mut definitions);
+
+    let mut footnotes = Footnotes::new();
+    for definition in definitions {
+        if let Node::FootnoteDefinition { label, span, .. } = &definition {
+            let key = normalize(label);
+            if footnotes.defined.contains_key(&key) {
+                footnotes.diagnostics.push(Diagnostic::new(format!("duplicate footnote '{}'", label), *span));
+            } else {
+                footnotes.order.push(key.clone());
+                footnotes.defined.insert(key, definition);
+            }
+        }
+    }
+    if footnotes.defined.is_empty() && !has_references(nodes) {
+        return Vec::new();
+    }
+
+    footnotes.number(nodes);
+    // Numbering a footnote can reference more footnotes, which are numbered after it.
+    let mut i = 0;
+    while i < footnotes.numbered.len() {
+        let mut children = match footnotes.numbered[i].children_mut() {
+            Some(children) => mem::take(children),
+            None => Vec::new(),
+        };
+        footnotes.number(&mut children);
+        if let Some(c) = footnotes.numbered[i].children_mut() {
+            *c = children;
+        }
+        i += 1;
+    }
+
+    for key in footnotes.order.iter() {
+        if let Some(Node::FootnoteDefinition { label, span, .. }) = footnotes.defined.get(key) {
+            footnotes.diagnostics.push(Diagnostic::new(format!("unused footnote '{}'", label), *span));
+        }
+    }
+    if !footnotes.numbered.is_empty() {
+        let begin = footnotes.numbered.iter().map(|n| n.span().begin).min().unwrap_or(0);
+        let end = footnotes.numbered.iter().map(|n| n.span().end).max().unwrap_or(0);
+        nodes.push(Node::Footnotes { children: footnotes.numbered, span: Span::new(begin, end) });
+    }
+
+    footnotes.diagnostics
+}
+
+/// Returns the id of the footnote numbered `number` in the html output.
+pub fn definition_id(number: usize) -> String {
+    format!("fn-{}", number)
+}
+
+/// Returns the id of a footnote reference, only the first reference to a footnote has no `index` suffix.
+pub fn reference_id(number: usize, index: usize) -> String {
+    if index > 1 {
+        format!("fnref-{}-{}", number, index)
+    } else {
+        format!("fnref-{}", number)
+    }
+}
+
+/// Removes the footnote definitions from the nodes and their children, in document order.
+fn take_definitions(nodes: &mut Vec<Node>, definitions: &mut Vec<Node>) {
+    for node in mem::take(nodes) {
+        match node {
+            Node::FootnoteDefinition { .. } => definitions.push(node),
+            mut node => {
+                if let Some(children) = node.children_mut() {
+                    take_definitions(children, definitions);
+                }
+                nodes.push(node);
+            },
+        }
+    }
+}
+
+fn has_references(nodes: &[Node]) -> bool {
+    nodes.iter().any(|n| matches!(n, Node::FootnoteReference { .. }) || has_references(n.children()))
+}
+
+/// Keeps track of the footnotes while numbering the references.
+struct Footnotes {
+    /// The definitions that weren't referenced yet, by their normalized label.
+    defined: HashMap<String, Node>,
+    /// The normalized labels in document order, so unused footnotes are reported in order.
+    order: Vec<String>,
+    /// The referenced definitions, ordered by their number.
+    numbered: Vec<Node>,
+    /// The index in `numbered` of each referenced footnote, by its normalized label.
+    labels: HashMap<String, usize>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Footnotes {
+    fn new() -> Footnotes {
+        Footnotes {
+            defined: HashMap::new(),
+            order: Vec::new(),
+            numbered: Vec::new(),
+            labels: HashMap::new(),
+            diagnostics: Vec::new(),
+        }
+    }
+
+    /// Numbers the references in the nodes and their children.
+    fn number(&mut self, nodes: &mut Vec<Node>) {
+        for mut node in mem::take(nodes) {
+            if let Node::FootnoteReference { label, number, index, span } = &mut node {
+                let key = normalize(label);
+                if let Some(definition) = self.defined.remove(&key) {
+                    self.numbered.push(definition);
+                    self.labels.insert(key.clone(), self.numbered.len() - 1);
+                }
+                match self.labels.get(&key) {
+                    Some(&i) => {
+                        if let Node::FootnoteDefinition { number: n, references, .. } = &mut self.numbered[i] {
+                            *n = i + 1;
+                            *references += 1;
+                            *number = i + 1;
+                            *index = *references;
+                        }
+                    },
+                    None => {
+                        self.diagnostics.push(Diagnostic::new(format!("undefined footnote '{}'", label), *span));
+                        node = Node::Text { text: format!("[^{}]", label), span: *span };
+                    },
+                }
+            } else if let Some(children) = node.children_mut() {
+                self.number(children);
+            }
+            push(nodes, node);
+        }
+    }
+}
+
+/// Pushes the node, merging it into the previous node if both are adjacent text.
+fn push(nodes: &mut Vec<Node>, node: Node) {
+    if let (Some(Node::Text { text, span }), Node::Text { text: next, span: next_span }) = (nodes.last_mut(), &node) {
+        if span.end == next_span.begin {
+            text.push_str(next);
+            span.end = next_span.end;
+            return;
+        }
+    }
+    nodes.push(node);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reference(label: &str, begin: usize) -> Node {
+        Node::FootnoteReference { label: label.to_string(), number: 0, index: 0, span: Span::new(begin, begin + label.len() + 3) }
+    }
+
+    fn definition(label: &str, begin: usize, children: Vec<Node>) -> Node {
+        Node::FootnoteDefinition { label: label.to_string(), number: 0, references: 0, children, span: Span::new(begin, begin + 10) }
+    }
+
+    #[test]
+    fn numbering() {
+        let mut nodes = vec![
+            definition("b", 0, vec![reference("c", 5)]),
+            Node::Paragraph { children: vec![reference("a", 20), reference("B", 30), reference("x", 40), reference("a", 50)], span: Span::new(20, 60) },
+            definition("a", 60, Vec::new()),
+            definition("a", 70, Vec::new()),
+            definition("c", 80, Vec::new()),
+            definition("unused", 90, Vec::new()),
+        ];
+        let diagnostics = collect(&mut nodes);
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(messages, vec!["duplicate footnote 'a'", "undefined footnote 'x'", "unused footnote 'unused'"]);
+
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[0].children()[0], Node::FootnoteReference { label: "a".to_string(), number: 1, index: 1, span: Span::new(20, 24) });
+        assert_eq!(nodes[0].children()[1], Node::FootnoteReference { label: "B".to_string(), number: 2, index: 1, span: Span::new(30, 34) });
+        assert_eq!(nodes[0].children()[2], Node::Text { text: "[^x]".to_string(), span: Span::new(40, 44) });
+        assert_eq!(nodes[0].children()[3], Node::FootnoteReference { label: "a".to_string(), number: 1, index: 2, span: Span::new(50, 54) });
+        let numbers: Vec<(String, usize, usize)> = nodes[1].children().iter().map(|n| match n {
+            Node::FootnoteDefinition { label, number, references, .. } => (label.clone(), *number, *references),
+            n => panic!("Unexpected node {:#?}", n),
+        }).collect();
+        assert_eq!(numbers, vec![("a".to_string(), 1, 2), ("b".to_string(), 2, 1), ("c".to_string(), 3, 1)]);
+        assert_eq!(nodes[1].span(), Span::new(0, 90));
+    }
+}
@@ -1,22 +1,39 @@
+use crate::error::Error;
 use crate::token::Token;
 use crate::token::TokenType;
 use crate::position::Position;
 use crate::emphasis;
 use crate::markdown;
+use crate::options::ParseOptions;
+use crate::paragraph;
+use crate::reference;
 use crate::table;
-use crate::wrapper;
 use crate::wrapper::CharsWithPosition;
 
-pub fn lex(text: &String) -> Vec<Token> {
+/// Converts the text to tokens and resolves its reference links.
+pub fn lex(text: &str, options: &ParseOptions) -> Result<Vec<Token>, Error> {
+    Ok(reference::resolve(text, lex_content(text, options, 0)?))
+}
+
+/// Converts the text to tokens without resolving reference links.
+/// Used for the content of list items, whose links may refer to definitions anywhere in the document.
+pub fn lex_content(text: &str, options: &ParseOptions, depth: usize) -> Result<Vec<Token>, Error> {
     let mut tokens: Vec<Token> = Vec::with_capacity(text.len());
-    let mut iter = CharsWithPosition::new(Position::new(), text.chars().enumerate().peekable());
+    let mut iter = CharsWithPosition::new(Position::new(), text.char_indices().peekable());
     let mut emphasis: emphasis::State = emphasis::State::new();
     let mut table: table::State = table::State::new();
+    markdown::match_list_start(text, &mut tokens, &mut iter, options, false, depth)?;
+    if options.tables && text.starts_with('|') {
+        // A table can start the text the same way it can follow a blank line, like inside of a blockquote.
+        table.possible_table = true;
+        table.possible_table_start = 0;
+        table.table_index = tokens.len();
+    }
     loop {
         match iter.next() {
             Some(c) => {
                 match c.1 {
-                    '#' => {
+                    '#' if options.headings => {
                         markdown::match_heading(&text, &mut tokens, &mut iter, c);
                     },
                     '-' => {
@@ -24,30 +41,33 @@ pub fn lex(text: &String) -> Vec<Token> {
                             Some(v) => {
                                 match v.1 {
                                     '-' => markdown::match_horizontalrule(text, &mut tokens, &mut iter, c),
-                                    ' ' => markdown::match_checkbutton(text, &mut tokens, &mut iter, c),
+                                    ' ' if options.checkbuttons => markdown::match_checkbutton(text, &mut tokens, &mut iter, c),
                                     _ => tokens.push(Token::new_single(TokenType::Text, c.0)),
                                 }
                             },
                             None => tokens.push(Token::new_single(TokenType::Text, c.0)),
                         }
                     },
-                    '!' => {
+                    '!' if options.images => {
                         markdown::match_image(text, &mut tokens, &mut iter, c);
                     },
-                    '[' => {
+                    '[' if options.footnotes && markdown::match_footnote_definition(text, &mut tokens, &mut iter, options, c, depth)? => (),
+                    '[' if options.footnotes && markdown::match_footnote_reference(text, &mut tokens, &mut iter, c) => (),
+                    '[' if options.links => {
                         markdown::match_link(text, &mut tokens, &mut iter, c);
                     },
-                    '<' => {
-                        markdown::match_html(&mut tokens, &mut iter, c);
-                    }
-                    '>' => {
-                        markdown::match_blockquote(&mut emphasis, text, &mut tokens, &mut iter, c);
+                    '<' if options.links && markdown::match_autolink(text, &mut tokens, &mut iter, c) => (),
+                    '<' if options.html => {
+                        markdown::match_html(text, &mut tokens, &mut iter, c);
                     },
-                    '`' => {
+                    'h'|'w' if options.bare_urls => markdown::match_bare_url(text, &mut tokens, &mut iter, c),
+                    '>' if options.blockquotes => markdown::match_blockquote(text, &mut tokens, &mut iter, options, c, depth)?,
+                    '`'|'~' if options.code_blocks && markdown::match_codeblock(text, &mut tokens, &mut iter, c)? => (),
+                    '`' if options.code_spans => {
                         match iter.peek() {
                             Some(v) => {
                                 match v.1 {
-                                    '`' => markdown::match_codeblock(text, &mut tokens, &mut iter, c),
+                                    '`' => tokens.push(Token::new_single(TokenType::Text, c.0)),
                                     _ => markdown::match_code(text, &mut tokens, &mut iter, c),
                                 }
                             },
@@ -57,6 +77,10 @@ pub fn lex(text: &String) -> Vec<Token> {
                     ' ' => tokens.push(Token::new_single(TokenType::Space, c.0)),
                     '\n' => {  // TODO break this off into its own function
                         tokens.push(Token::new_single(TokenType::Newline, c.0));
+                        if markdown::match_list_start(text, &mut tokens, &mut iter, options, true, depth)?
+                            || (options.headings && markdown::match_heading_underline(text, &mut tokens, &mut iter)) {
+                            continue;
+                        }
                         match iter.peek() {
                             Some(v) => {
                                 match v.1 {
@@ -68,60 +92,36 @@ pub fn lex(text: &String) -> Vec<Token> {
                                         }
                                         tokens.push(Token::new_single(TokenType::Newline, v.0));
                                         iter.next();
+                                        if markdown::match_list_start(text, &mut tokens, &mut iter, options, false, depth)? {
+                                            continue;
+                                        }
                                         match iter.peek() {
                                             Some(v) => match v.1 {
-                                                '|' => {
+                                                '|' if options.tables => {
                                                     if !table.possible_table {
                                                         table.possible_table = true;
                                                         table.possible_table_start = v.0;
                                                         table.table_index = tokens.len();
                                                     }
                                                 },
-                                                '*' => {
+                                                '*' if options.emphasis => {
                                                     iter.next();
                                                     if let Some(v) = iter.peek() {
                                                         match v.1 {
-                                                            ' ' => markdown::match_list(
-                                                                wrapper::ListType(TokenType::UnorderedListBegin, TokenType::UnorderedListEnd),
-                                                                text,
-                                                                &mut tokens,
-                                                                &mut iter,
-                                                                (c.0 + 1, '_')
-                                                            ),
                                                             '\n' => (),
                                                             // "c.0 + 1" To step over opening newline.
                                                             _ => markdown::match_emphasis(&mut emphasis, text, &mut tokens, &mut iter, (c.0 + 1, '*')),
                                                         }
                                                     } else {
-                                                        tokens.push(Token::new_single(TokenType::Text, iter.index()));
+                                                        tokens.push(Token::new_single(TokenType::Text, iter.last()));
                                                     }
                                                 },
-                                                '1' => {
+                                                ' ' if options.indent_blocks => {
+                                                    // The block starts after the newline ending the blank line.
+                                                    let newline = (v.0 - 1, '\n');
                                                     iter.next();
-                                                    if let Some(v) = iter.peek() {
-                                                        match v.1 {
-                                                            '.' => {
-                                                                iter.next();
-                                                                if let Some(v) = iter.peek() {
-                                                                    match v.1 {
-                                                                        ' ' => markdown::match_list(
-                                                                            wrapper::ListType(TokenType::OrderedListBegin, TokenType::OrderedListEnd),
-                                                                            text,
-                                                                            &mut tokens,
-                                                                            &mut iter,
-                                                                            (c.0 + 1, '_')
-                                                                        ),
-                                                                        '\n' => (),
-                                                                        _ => tokens.push(Token::new_single(TokenType::Text, iter.index())),
-                                                                    }
-                                                                }
-                                                            },
-                                                            '\n' => (),
-                                                            _ => tokens.push(Token::new_single(TokenType::Text, iter.index())),
-                                                        }
-                                                    }
+                                                    markdown::match_indentblock(text, &mut tokens, &mut iter, newline);
                                                 },
-                                                ' ' => markdown::match_indentblock(text, &mut tokens, &mut iter, c),
                                                 _ => (),
                                             },
                                             None => (),
@@ -146,58 +146,24 @@ pub fn lex(text: &String) -> Vec<Token> {
                                         match iter.peek() {
                                             Some(v) => {
                                                 match v.1 {
-                                                    ' ' => markdown::match_indentblock(text, &mut tokens, &mut iter, c),
-                                                    _ => {
-                                                        tokens.push(Token::new_single(TokenType::Space, v.0));
-                                                        iter.next();
-                                                    },
+                                                    ' ' if options.indent_blocks => markdown::match_indentblock(text, &mut tokens, &mut iter, c),
+                                                    // "c.0 + 1" Is the space following the newline.
+                                                    _ => tokens.push(Token::new_single(TokenType::Space, c.0 + 1)),
                                                 }
                                             },
-                                            None => tokens.push(Token::new_single(TokenType::Space, c.0)),
+                                            None => tokens.push(Token::new_single(TokenType::Space, c.0 + 1)),
                                         }
                                     },
-                                    '*' => {
+                                    '*' if options.emphasis => {
                                         iter.next();
                                         if let Some(v) = iter.peek() {
                                             match v.1 {
-                                                ' ' => markdown::match_list(
-                                                    wrapper::ListType(TokenType::UnorderedListBegin, TokenType::UnorderedListEnd),
-                                                    text,
-                                                    &mut tokens,
-                                                    &mut iter,
-                                                    (c.0 + 1, '_')
-                                                ),
                                                 '\n' => (),
                                                 // "c.0 + 1" To step over opening newline.
                                                 _ => markdown::match_emphasis(&mut emphasis, text, &mut tokens, &mut iter, (c.0 + 1, '*')),
                                             }
                                         } else {
-                                            tokens.push(Token::new_single(TokenType::Text, iter.index()));
-                                        }
-                                    },
-                                    '1' => {
-                                        iter.next();
-                                        if let Some(v) = iter.peek() {
-                                            match v.1 {
-                                                '.' => {
-                                                    iter.next();
-                                                    if let Some(v) = iter.peek() {
-                                                        match v.1 {
-                                                            ' ' => markdown::match_list(
-                                                                wrapper::ListType(TokenType::OrderedListBegin, TokenType::OrderedListEnd),
-                                                                text,
-                                                                &mut tokens,
-                                                                &mut iter,
-                                                                (c.0 + 1, '_')
-                                                            ),
-                                                            '\n' => (),
-                                                            _ => tokens.push(Token::new_single(TokenType::Text, iter.index())),
-                                                        }
-                                                    }
-                                                },
-                                                '\n' => (),
-                                                _ => tokens.push(Token::new_single(TokenType::Text, iter.index())),
-                                            }
+                                            tokens.push(Token::new_single(TokenType::Text, iter.last()));
                                         }
                                     },
                                     _ => (),
@@ -212,11 +178,13 @@ pub fn lex(text: &String) -> Vec<Token> {
                             },
                         }
                     },
-                    '*'|'~'|'_' => markdown::match_emphasis(&mut emphasis, text, &mut tokens, &mut iter, c),
+                    '*' if options.emphasis => markdown::match_emphasis(&mut emphasis, text, &mut tokens, &mut iter, c),
+                    '~' if options.strike => markdown::match_emphasis(&mut emphasis, text, &mut tokens, &mut iter, c),
+                    '_' if options.underline => markdown::match_emphasis(&mut emphasis, text, &mut tokens, &mut iter, c),
                     '|' => tokens.push(Token::new_single(TokenType::Pipe, c.0)),
                     '\t' => tokens.push(Token::new_single(TokenType::Tab, c.0)),
                     '\\' => tokens.push(Token::new_single(TokenType::Escape, c.0)),
-                    _ => tokens.push(Token::new_single(TokenType::Text, c.0)),
+                    _ => tokens.push(Token::new_char(TokenType::Text, c)),
                 }
             },
             None => {
@@ -229,18 +197,17 @@ pub fn lex(text: &String) -> Vec<Token> {
         }
     }
 
-    tokens
+    Ok(paragraph::insert_paragraphs(text, tokens))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io;
     use std::fs;
 
     #[test]
-    fn heading() -> Result<(), io::Error> {
-        let t = lex(&fs::read_to_string("tests/heading.md")?);
+    fn heading() -> Result<(), Error> {
+        let t = lex(&fs::read_to_string("tests/heading.md")?, &ParseOptions::new())?;
         let mut headings: usize = 0;
         let mut errors: usize = 0;
         for token in t.iter() {
@@ -251,7 +218,7 @@ mod tests {
                 TokenType::Error => {
                     errors += 1;
                 },
-                TokenType::Text|TokenType::Space|TokenType::Newline => (),
+                TokenType::Text|TokenType::Space|TokenType::Newline|TokenType::ParagraphBegin|TokenType::ParagraphEnd => (),
                 _ => panic!("Encounterd TokenType other than expected!"),
             }
         }
@@ -262,26 +229,32 @@ mod tests {
     }
 
     #[test]
-    fn checkbutton() -> Result<(), io::Error> {
-        let t = lex(&fs::read_to_string("tests/checkbutton.md")?);
+    fn checkbutton() -> Result<(), Error> {
+        let t = lex(&fs::read_to_string("tests/checkbutton.md")?, &ParseOptions::new())?;
         let mut checkbuttons: usize = 0;
+        let mut items: usize = 0;
         for token in t.iter() {
             match token.id {
                 TokenType::Checkbutton(bool) => {
                     checkbuttons += 1;
                 },
-                TokenType::Text|TokenType::Space|TokenType::Newline => (),
+                TokenType::ListItemBegin => {
+                    items += 1;
+                },
+                TokenType::Text|TokenType::Space|TokenType::Newline|TokenType::ListItemEnd
+                |TokenType::UnorderedListBegin|TokenType::UnorderedListEnd|TokenType::ParagraphBegin|TokenType::ParagraphEnd => (),
                 _ => panic!("Encounterd TokenType other than expected!"),
             }
         }
         assert!(checkbuttons == 2);
+        assert!(items == 5);
 
         Ok(())
     }
 
     #[test]
-    fn image() -> Result<(), io::Error> {
-        let t = lex(&fs::read_to_string("tests/image.md")?);
+    fn image() -> Result<(), Error> {
+        let t = lex(&fs::read_to_string("tests/image.md")?, &ParseOptions::new())?;
         let mut image_alt: usize = 0;
         let mut image_src: usize = 0;
         let mut errors: usize = 0;
@@ -296,7 +269,7 @@ mod tests {
                 TokenType::Error => {
                     errors += 1;
                 },
-                TokenType::Text|TokenType::Space|TokenType::Newline => (),
+                TokenType::Text|TokenType::Space|TokenType::Newline|TokenType::ParagraphBegin|TokenType::ParagraphEnd => (),
                 _ => panic!("Encounterd TokenType other than expected!"),
             }
         }
@@ -308,10 +281,14 @@ mod tests {
     }
 
     #[test]
-    fn link() -> Result<(), io::Error> {
-        let t = lex(&fs::read_to_string("tests/link.md")?);
+    fn link() -> Result<(), Error> {
+        let t = lex(&fs::read_to_string("tests/link.md")?, &ParseOptions::new())?;
         let mut link_text: usize = 0;
         let mut link_href: usize = 0;
+        let mut titles: usize = 0;
+        let mut autolinks: usize = 0;
+        let mut references: usize = 0;
+        let mut definitions: usize = 0;
         let mut errors: usize = 0;
         for token in t.iter() {
             match token.id {
@@ -321,30 +298,75 @@ mod tests {
                 TokenType::LinkHref => {
                     link_href += 1;
                 },
+                TokenType::LinkTitle => {
+                    titles += 1;
+                },
+                TokenType::Autolink => {
+                    autolinks += 1;
+                },
+                TokenType::LinkReference => {
+                    references += 1;
+                },
+                TokenType::LinkDefinition => {
+                    definitions += 1;
+                },
                 TokenType::Error => {
                     errors += 1;
                 },
-                TokenType::Text|TokenType::Space|TokenType::Newline => (),
+                TokenType::Text|TokenType::Space|TokenType::Newline|TokenType::ParagraphBegin|TokenType::ParagraphEnd => (),
                 _ => panic!("Encounterd TokenType other than expected!"),
             }
         }
-        assert!(link_text == 2);
-        assert!(link_href == 2);
+        assert!(link_text == 8);
+        assert!(link_href == 7);
+        assert!(titles == 4);
+        assert!(autolinks == 2);
+        assert!(references == 4);
+        assert!(definitions == 1);
         assert!(errors == 1);
 
         Ok(())
     }
 
     #[test]
-    fn horizontalrule() -> Result<(), io::Error> {
-        let t = lex(&fs::read_to_string("tests/horizontalrule.md")?);
+    fn footnote() -> Result<(), Error> {
+        let t = lex(&fs::read_to_string("tests/footnote.md")?, &ParseOptions::new())?;
+        let mut references: usize = 0;
+        let mut fb: usize = 0;
+        let mut fe: usize = 0;
+        for token in t.iter() {
+            match token.id {
+                TokenType::FootnoteReference => {
+                    references += 1;
+                },
+                TokenType::FootnoteDefinitionBegin => {
+                    fb += 1;
+                },
+                TokenType::FootnoteDefinitionEnd => {
+                    fe += 1;
+                },
+                TokenType::Text|TokenType::Space|TokenType::Newline|TokenType::ParagraphBegin|TokenType::ParagraphEnd
+                |TokenType::ItalicBegin|TokenType::ItalicEnd => (),
+                _ => panic!("Encounterd TokenType other than expected!"),
+            }
+        }
+        assert!(references == 4);
+        assert!(fb == 3);
+        assert!(fe == 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn horizontalrule() -> Result<(), Error> {
+        let t = lex(&fs::read_to_string("tests/horizontalrule.md")?, &ParseOptions::new())?;
         let mut hr: usize = 0;
         for token in t.iter() {
             match token.id {
                 TokenType::HorizontalRule => {
                     hr += 1;
                 },
-                TokenType::Text|TokenType::Space|TokenType::Newline => (),
+                TokenType::Text|TokenType::Space|TokenType::Newline|TokenType::ParagraphBegin|TokenType::ParagraphEnd => (),
                 _ => panic!("Encounterd TokenType other than expected!"),
             }
         }
@@ -354,10 +376,11 @@ mod tests {
     }
 
     #[test]
-    fn blockqoute() -> Result<(), io::Error> {
-        let t = lex(&fs::read_to_string("tests/blockquote.md")?);
+    fn blockqoute() -> Result<(), Error> {
+        let t = lex(&fs::read_to_string("tests/blockquote.md")?, &ParseOptions::new())?;
         let mut bb: usize = 0;
         let mut be: usize = 0;
+        let mut alerts: usize = 0;
         for token in t.iter() {
             match token.id {
                 TokenType::BlockquoteBegin => {
@@ -366,29 +389,45 @@ mod tests {
                 TokenType::BlockquoteEnd => {
                     be += 1;
                 },
-                TokenType::Text|TokenType::Space|TokenType::Newline|TokenType::ItalicBegin
+                TokenType::BlockquoteAlert => {
+                    alerts += 1;
+                },
+                TokenType::Text|TokenType::Space|TokenType::Newline|TokenType::ParagraphBegin|TokenType::ParagraphEnd|TokenType::ItalicBegin
                 |TokenType::BoldBegin|TokenType::UnderlineBegin|TokenType::StrikeBegin
                 |TokenType::ItalicEnd|TokenType::BoldEnd|TokenType::UnderlineEnd|TokenType::StrikeEnd
-                |TokenType::Html => (),
+                |TokenType::Html|TokenType::LinkText|TokenType::LinkHref|TokenType::Code|TokenType::Heading
+                |TokenType::UnorderedListBegin|TokenType::UnorderedListEnd|TokenType::ListItemBegin|TokenType::ListItemEnd => (),
                 _ => panic!("Encounterd TokenType other than expected!"),
             }
         }
-        assert!(bb == 3);
-        assert!(be == 3);
+        assert!(bb == 7);
+        assert!(be == 7);
+        assert!(alerts == 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn blockquote_nesting() -> Result<(), Error> {
+        let text = format!("{} x\n", ">".repeat(10000));
+        let t = lex(&text, &ParseOptions::new())?;
+        let quotes = t.iter().filter(|t| t.id == TokenType::BlockquoteBegin).count();
+        assert_eq!(quotes, markdown::MAX_NESTING);
+        assert_eq!(quotes, t.iter().filter(|t| t.id == TokenType::BlockquoteEnd).count());
 
         Ok(())
     }
 
     #[test]
-    fn code() -> Result<(), io::Error> {
-        let t = lex(&fs::read_to_string("tests/code.md")?);
+    fn code() -> Result<(), Error> {
+        let t = lex(&fs::read_to_string("tests/code.md")?, &ParseOptions::new())?;
         let mut code: usize = 0;
         for token in t.iter() {
             match token.id {
                 TokenType::Code => {
                     code += 1;
                 },
-                TokenType::Text|TokenType::Space|TokenType::Newline => (),
+                TokenType::Text|TokenType::Space|TokenType::Newline|TokenType::ParagraphBegin|TokenType::ParagraphEnd => (),
                 _ => panic!("Encounterd TokenType other than expected!"),
             }
         }
@@ -398,16 +437,20 @@ mod tests {
     }
 
     #[test]
-    fn codeblock() -> Result<(), io::Error> {
-        let t = lex(&fs::read_to_string("tests/codeblock.md")?);
+    fn codeblock() -> Result<(), Error> {
+        let t = lex(&fs::read_to_string("tests/codeblock.md")?, &ParseOptions::new())?;
         let mut cbb: usize = 0;
         let mut cbe: usize = 0;
         let mut cbl: usize = 0;
+        let mut cba: usize = 0;
         for token in t.iter() {
             match token.id {
                 TokenType::CodeBlockBegin => {
                     cbb += 1;
                 },
+                TokenType::CodeBlockAttribute => {
+                    cba += 1;
+                },
                 TokenType::CodeBlockEnd => {
                     cbe += 1;
                 },
@@ -417,23 +460,24 @@ mod tests {
                 _ => (),
             }
         }
-        assert!(cbb == 6);
-        assert!(cbl == 5);
-        assert!(cbe == 5);
+        assert!(cbb == 10);
+        assert!(cbl == 10);
+        assert!(cbe == 10);
+        assert!(cba == 7);
 
         Ok(())
     }
 
     #[test]
-    fn indentblock() -> Result<(), io::Error> {
-        let t = lex(&fs::read_to_string("tests/indentblock.md")?);
+    fn indentblock() -> Result<(), Error> {
+        let t = lex(&fs::read_to_string("tests/indentblock.md")?, &ParseOptions::new())?;
         let mut indent: usize = 0;
         for token in t.iter() {
             match token.id {
                 TokenType::IndentBlock => {
                     indent += 1;
                 },
-                TokenType::Text|TokenType::Space|TokenType::Newline => (),
+                TokenType::Text|TokenType::Space|TokenType::Newline|TokenType::ParagraphBegin|TokenType::ParagraphEnd => (),
                 _ => panic!("Encounterd TokenType other than expected!"),
             }
         }
@@ -443,15 +487,15 @@ mod tests {
     }
 
     #[test]
-    fn escape() -> Result<(), io::Error> {
-        let t = lex(&fs::read_to_string("tests/escape.md")?);
+    fn escape() -> Result<(), Error> {
+        let t = lex(&fs::read_to_string("tests/escape.md")?, &ParseOptions::new())?;
         let mut esc: usize = 0;
         for token in t.iter() {
             match token.id {
                 TokenType::Escape => {
                     esc += 1;
                 },
-                TokenType::Text|TokenType::Space|TokenType::Newline|TokenType::Heading
+                TokenType::Text|TokenType::Space|TokenType::Newline|TokenType::ParagraphBegin|TokenType::ParagraphEnd|TokenType::Heading
                 |TokenType::ItalicBegin|TokenType::ItalicEnd|TokenType::BoldBegin|TokenType::BoldEnd
                 |TokenType::StrikeBegin|TokenType::StrikeEnd|TokenType::UnderlineBegin|TokenType::UnderlineEnd => (),
                 _ => panic!("Encounterd TokenType other than expected!"),
@@ -463,8 +507,8 @@ mod tests {
     }
 
     #[test]
-    fn emphasis() -> Result<(), io::Error> {
-        let t = lex(&fs::read_to_string("tests/emphasis.md")?);
+    fn emphasis() -> Result<(), Error> {
+        let t = lex(&fs::read_to_string("tests/emphasis.md")?, &ParseOptions::new())?;
         let mut i: usize = 0;
         let mut b: usize = 0;
         let mut s: usize = 0;
@@ -483,7 +527,7 @@ mod tests {
                 TokenType::UnderlineBegin|TokenType::UnderlineEnd => {
                     u += 1;
                 },
-                TokenType::Text|TokenType::Space|TokenType::Newline => (),
+                TokenType::Text|TokenType::Space|TokenType::Newline|TokenType::ParagraphBegin|TokenType::ParagraphEnd => (),
                 _ => panic!("Encounterd TokenType other than expected!"),
             }
         }
@@ -496,11 +540,11 @@ mod tests {
     }
 
     #[test]
-    fn html() -> Result<(), io::Error> {
-        let t = lex(&fs::read_to_string("tests/html.md")?);
+    fn html() -> Result<(), Error> {
+        let t = lex(&fs::read_to_string("tests/html.md")?, &ParseOptions::new())?;
         for token in t.iter() {
             match token.id {
-                TokenType::Text|TokenType::Space|TokenType::Newline|TokenType::Html => (),
+                TokenType::Text|TokenType::Space|TokenType::Newline|TokenType::ParagraphBegin|TokenType::ParagraphEnd|TokenType::Html => (),
                 _ => panic!(format!("Encounterd TokenType other than expected! {:#?}", token)),
             }
         }
@@ -509,8 +553,20 @@ mod tests {
     }
 
     #[test]
-    fn table() -> Result<(), io::Error> {
-        let t = lex(&fs::read_to_string("tests/table.md")?);
+    fn html_unclosed() -> Result<(), Error> {
+        let text = "word < word\n".repeat(20000);
+        let t = lex(&text, &ParseOptions::new())?;
+        assert!(!t.iter().any(|t| t.id == TokenType::Html));
+        let text = format!("{}<b>\n", "a <b c\n\n".repeat(10000));
+        let t = lex(&text, &ParseOptions::new())?;
+        assert_eq!(t.iter().filter(|t| t.id == TokenType::Html).count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn table() -> Result<(), Error> {
+        let t = lex(&fs::read_to_string("tests/table.md")?, &ParseOptions::new())?;
         let mut p: usize = 0;
         let mut tb: usize = 0;
         let mut te: usize = 0;
@@ -591,8 +647,8 @@ mod tests {
     }
 
     #[test]
-    fn list() -> Result<(), io::Error> {
-        let t = lex(&fs::read_to_string("tests/list.md")?);
+    fn list() -> Result<(), Error> {
+        let t = lex(&fs::read_to_string("tests/list.md")?, &ParseOptions::new())?;
         let mut ulb: usize = 0;
         let mut ule: usize = 0;
         let mut olb: usize = 0;
@@ -638,12 +694,12 @@ mod tests {
                 _ => (),
             }
         }
-        assert!(ulb == 8);
-        assert!(ule == 8);
+        assert!(ulb == 9);
+        assert!(ule == 9);
         assert!(olb == 3);
         assert!(ole == 3);
-        assert!(lib == 19);
-        assert!(lie == 19); 
+        assert!(lib == 21);
+        assert!(lie == 21);
 
         assert!(ib == 2);
         assert!(ie == 2);
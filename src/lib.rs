@@ -11,33 +11,142 @@
 #![allow(dead_code, unused_variables)] // TODO remove this once ready
 
 use std::fs;
-use std::io;
 
+mod ast;
+mod diagnostic;
+mod error;
+mod escape;
+mod footnote;
 mod position;
 mod token;
 mod emphasis;
 mod markdown;
+mod options;
 mod lexer;
+mod paragraph;
 mod parser;
+mod reference;
+mod renderer;
+mod sanitize;
+mod slug;
 mod table;
+mod toc;
 mod wrapper;
 mod syntax;
 
+pub use ast::Alignment;
+pub use ast::Document;
+pub use ast::Emphasis;
+pub use ast::Highlight;
+pub use ast::Node;
+pub use ast::Span;
+pub use diagnostic::Diagnostic;
+pub use error::Error;
+pub use options::AlertKind;
+pub use options::ParseOptions;
+pub use escape::escape_attribute;
+pub use escape::escape_code;
+pub use escape::escape_text;
+pub use position::LineIndex;
+pub use position::Position;
+pub use renderer::HtmlRenderer;
+pub use renderer::Renderer;
+pub use sanitize::sanitize;
+pub use sanitize::HtmlPolicy;
+pub use sanitize::SafeMode;
+pub use slug::slugify;
+pub use slug::Slugger;
+pub use toc::TocEntry;
 pub use token::Token;
 pub use token::TokenType;
 
+/// Converts markdown text to a vector of tokens.
+///
+/// The `begin` and `end` byte offsets of the tokens can be turned
+/// into line and column positions with a `LineIndex`.
+pub fn lex(text: &str) -> Result<Vec<Token>, Error> {
+    lexer::lex(text, &ParseOptions::new())
+}
+
+/// Same as `lex` but only the constructs enabled in `options` are recognized.
+pub fn lex_with_options(text: &str, options: &ParseOptions) -> Result<Vec<Token>, Error> {
+    lexer::lex(text, options)
+}
+
+/// Converts markdown text to a document tree.
+pub fn parse(text: &str) -> Result<Document, Error> {
+    parse_with_options(text, &ParseOptions::new())
+}
+
+/// Same as `parse` but only the constructs enabled in `options` are recognized.
+pub fn parse_with_options(text: &str, options: &ParseOptions) -> Result<Document, Error> {
+    let tokens = lexer::lex(text, options)?;
+    parser::parse(text, &tokens, options)
+}
+
+/// Returns the headings of the markdown text as a hierarchy,
+/// every heading contains the headings of a higher level following it.
+pub fn toc(text: &str) -> Result<Vec<TocEntry>, Error> {
+    Ok(toc::table_of_contents(&parse(text)?.children))
+}
+
+/// Same as `toc` but uses the tokens already returned by `lex` for the text.
+pub fn toc_from_tokens(text: &str, tokens: &[Token]) -> Result<Vec<TocEntry>, Error> {
+    Ok(toc::table_of_contents(&parser::parse(text, tokens, &ParseOptions::new())?.children))
+}
+
+/// Converts markdown text to html.
+///
+/// This does not touch the file system, so it can be used to render
+/// markdown coming from any source. To embed css into the output
+/// have a look at `embed_css`.
+pub fn to_html(text: &str) -> Result<String, Error> {
+    render(text, &mut HtmlRenderer::new())
+}
+
+/// Same as `to_html` but only the constructs enabled in `options` are recognized,
+/// the markdown of disabled ones is output as text.
+pub fn to_html_with_options(text: &str, options: &ParseOptions) -> Result<String, Error> {
+    Ok(HtmlRenderer::new().render(&parse_with_options(text, options)?))
+}
+
+/// Converts untrusted markdown text to html with raw html and unsafe urls handled by `safe`.
+///
+/// Returns the html together with a `Diagnostic` for everything that was removed or escaped.
+pub fn to_safe_html(text: &str, safe: &SafeMode) -> Result<(String, Vec<Diagnostic>), Error> {
+    let mut document = parse(text)?;
+    let mut diagnostics = document.diagnostics.clone();
+    diagnostics.append(&mut sanitize(&mut document, safe));
+
+    Ok((HtmlRenderer::new().render(&document), diagnostics))
+}
+
+/// Converts markdown text to the output of the given renderer.
+pub fn render<R: Renderer>(text: &str, renderer: &mut R) -> Result<String, Error> {
+    Ok(renderer.render(&parse(text)?))
+}
+
+/// Prepends the css wrapped in a `<style>` element to the html.
+pub fn embed_css(html: &str, css: &str) -> String {
+    format!("<style>\n{}</style>\n{}", css, html)
+}
+
 /// Converts a markdown file to an html file.
 ///
 /// If you don't want to embed any css into the generated html,
 /// you can just pass an empty &str.
 ///
 /// Returns a vector of tokens if successful.
-/// The tokens can be used for syntax highlighting using the `begin` and `end` indices.
-pub fn markdown_to_html(input: &str, output: &str, css: &str) -> Result<Vec<Token>, io::Error> {
+/// The tokens can be used for syntax highlighting using the `begin` and `end` byte offsets.
+pub fn markdown_to_html(input: &str, output: &str, css: &str) -> Result<Vec<Token>, Error> {
     let text: String = fs::read_to_string(input)?;
-    let tokens = lexer::lex(&text);
-    let html = parser::parse(&text, &tokens);
-    parser::generate_html(output.to_string(), html, css);
+    let options = ParseOptions::new();
+    let tokens = lexer::lex(&text, &options)?;
+    let mut html = HtmlRenderer::new().render(&parser::parse(&text, &tokens, &options)?);
+    if !css.is_empty() {
+        html = embed_css(&html, &fs::read_to_string(css)?);
+    }
+    fs::write(output, html)?;
 
     Ok(tokens)
 }
@@ -45,12 +154,13 @@ pub fn markdown_to_html(input: &str, output: &str, css: &str) -> Result<Vec<Toke
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::io;
     use std::io::Write;
     use std::path::Path;
     use std::fs::create_dir;
 
     #[test]
-    fn log() -> Result<(), io::Error> {
+    fn log() -> Result<(), Error> {
         if !Path::new("generated_html").exists() {
             create_dir("generated_html").unwrap();
         }
@@ -74,11 +184,13 @@ mod tests {
             "html",
             "table",
             "list",
+            "footnote",
             "all",
             "md_readme",
             "mh_readme",
             "nm_readme",
             "eli5_readme",
+            "multilingual",
         ];
 
         for test in test_files.iter() {
@@ -94,6 +206,31 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn in_memory() -> Result<(), Error> {
+        let html = to_html("# heading\n\n**bold** text\n")?;
+        assert_eq!(html, "<h1 id=\"heading\">heading</h1>\n<p><b>bold</b> text</p>\n");
+        assert_eq!(embed_css(&html, "h1 {}\n"), format!("<style>\nh1 {{}}\n</style>\n{}", html));
+        let text = "# heading\n## sub\n";
+        assert_eq!(toc(text)?, toc_from_tokens(text, &lex(text)?)?);
+        assert_eq!(toc(text)?[0].children[0].id, "sub");
+
+        Ok(())
+    }
+
+    #[test]
+    fn errors() {
+        match markdown_to_html("tests/heading.md", "generated_html/heading.html", "css/missing.css") {
+            Err(Error::Io(_)) => (),
+            r => panic!("Expected io error, got {:?}", r),
+        }
+        let tokens = vec![Token::new(TokenType::ImageAlt, 2, 5)];
+        match parser::parse("![alt]", &tokens, &ParseOptions::new()) {
+            Err(Error::MalformedTokens { index: 1, expected: TokenType::ImageSrc }) => (),
+            r => panic!("Expected malformed tokens error, got {:?}", r),
+        }
+    }
+
     fn log_tokens(tokens: Vec<Token>, output: &str) -> Result<(), io::Error> {
         let mut log = fs::File::create(format!("log/{}.log", output.to_string()))?;
         log.write(format!("{:#?}", tokens).as_bytes())?;
@@ -1,15 +1,24 @@
+use crate::error::Error;
 use crate::token::Token;
 use crate::token::TokenType;
+use crate::ast::Span;
 use crate::emphasis::Tag;
 use crate::emphasis;
+use crate::lexer;
+use crate::options::ParseOptions;
+use crate::reference;
 use crate::table::Alignment;
 use crate::table;
 use crate::wrapper;
 use crate::wrapper::CharsWithPosition;
 use crate::syntax;
 
-pub fn match_heading(text: &String, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, c: (usize, char)) {
-    if c.0 == 0 || &text[c.0 - 1..c.0] == "\n" {
+/// How deep blockquotes, list items and footnotes can be nested inside of each other.
+/// Markers past it are text, so deeply nested input can't overflow the stack.
+pub const MAX_NESTING: usize = 32;
+
+pub fn match_heading(text: &str, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, c: (usize, char)) {
+    if is_line_start(text, c.0) {
         let mut heading_count: usize = 1;
         while let Some(v) = iter.next() {
             match v.1 {
@@ -36,7 +45,7 @@ pub fn match_heading(text: &String, tokens: &mut Vec<Token>, iter: &mut CharsWit
     }
 }
 
-pub fn match_checkbutton(text: &String, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, c: (usize, char)) {
+pub fn match_checkbutton(text: &str, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, c: (usize, char)) {
     match text.get(c.0 + 2..c.0 + 6) {
         Some(v) => {
             if v == "[ ] " {
@@ -53,7 +62,44 @@ pub fn match_checkbutton(text: &String, tokens: &mut Vec<Token>, iter: &mut Char
     }
 }
 
-pub fn match_image(text: &String, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, c: (usize, char)) {
+/// Matches a line of `=` or `-` under a line of text, which turns the text into a heading.
+///
+/// Only the lexer knows the underline, whether the text before it is a paragraph
+/// is decided by `paragraph::insert_paragraphs`.
+pub fn match_heading_underline(text: &str, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition) -> bool {
+    let begin = iter.index();
+    if begin == 0 || !text[..begin].ends_with('\n') {
+        return false;
+    }
+    let previous = &text[text[..begin - 1].rfind('\n').map_or(0, |i| i + 1)..begin - 1];
+    let line = &text[begin..line_end(text, begin)];
+    match heading_underline(line) {
+        Some((indent, length)) if !previous.trim().is_empty() => {
+            tokens.push(Token::new(TokenType::HeadingUnderline, begin + indent, begin + indent + length));
+            advance_to(iter, begin + line.len());
+            true
+        },
+        _ => false,
+    }
+}
+
+/// Returns the indentation and the length of the run of `=` or `-` making up a setext underline.
+fn heading_underline(line: &str) -> Option<(usize, usize)> {
+    let indent = line.bytes().take_while(|b| *b == b' ').count();
+    let run = &line[indent..];
+    let c = match run.bytes().next() {
+        Some(c) if indent <= 3 && (c == b'=' || c == b'-') => c,
+        _ => return None,
+    };
+    let length = run.bytes().take_while(|b| *b == c).count();
+    if !run[length..].trim().is_empty() {
+        return None;
+    }
+
+    Some((indent, length))
+}
+
+pub fn match_image(text: &str, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, c: (usize, char)) {
     match iter.peek() {
         Some(v) => {
             match v.1 {
@@ -64,31 +110,41 @@ pub fn match_image(text: &String, tokens: &mut Vec<Token>, iter: &mut CharsWithP
                         match v.1 {
                             ']' =>  {
                                 let alt_end: usize = v.0;
-                                match iter.peek() {
-                                    Some(v) => {
-                                        match v.1 {
-                                            '(' => {
-                                                let src_begin: usize = v.0 + 1;
-                                                while let Some(v) = iter.next() {
-                                                    match v.1 {
-                                                        ')' =>  {
-                                                            tokens.push(Token::new(TokenType::ImageAlt, alt_begin, alt_end));
-                                                            tokens.push(Token::new(TokenType::ImageSrc, src_begin, v.0));
-                                                            break;
-                                                        },
-                                                        '\n' => {
-                                                            tokens.push(Token::new(TokenType::Error, c.0, v.0));
-                                                            tokens.push(Token::new_single(TokenType::Newline, v.0));
-                                                            break;
-                                                        },
-                                                        _ => (),
-                                                    }
-                                                }
-                                            },
-                                            _ => tokens.push(Token::new(TokenType::Text, c.0, v.0)),
+                                let next = iter.peek().copied();
+                                let destination = match next {
+                                    Some((i, '(')) => link_destination(text, i + 1),
+                                    _ => None,
+                                };
+                                match (next, destination) {
+                                    (_, Some((src, title, end))) => {
+                                        tokens.push(Token::new(TokenType::ImageAlt, alt_begin, alt_end));
+                                        tokens.push(Token::new(TokenType::ImageSrc, src.begin, src.end));
+                                        if let Some(title) = title {
+                                            tokens.push(Token::new(TokenType::ImageTitle, title.begin, title.end));
+                                        }
+                                        advance_to(iter, end);
+                                    },
+                                    // Not a valid destination, everything up to the parenthesis is the url.
+                                    (Some((i, '(')), None) => {
+                                        let src_begin: usize = i + 1;
+                                        while let Some(v) = iter.next() {
+                                            match v.1 {
+                                                ')' =>  {
+                                                    tokens.push(Token::new(TokenType::ImageAlt, alt_begin, alt_end));
+                                                    tokens.push(Token::new(TokenType::ImageSrc, src_begin, v.0));
+                                                    break;
+                                                },
+                                                '\n' => {
+                                                    tokens.push(Token::new(TokenType::Error, c.0, v.0));
+                                                    tokens.push(Token::new_single(TokenType::Newline, v.0));
+                                                    break;
+                                                },
+                                                _ => (),
+                                            }
                                         }
                                     },
-                                    None => (),
+                                    (Some((i, _)), None) => tokens.push(Token::new(TokenType::Text, c.0, i)),
+                                    (None, None) => tokens.push(Token::new(TokenType::Text, c.0, text.len())),
                                 }
                                 break;
                             },
@@ -108,59 +164,205 @@ pub fn match_image(text: &String, tokens: &mut Vec<Token>, iter: &mut CharsWithP
     }
 }
 
-pub fn match_link(text: &String, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, c: (usize, char)) {
+/// Matches a `[^label]: text` footnote definition at the start of a line.
+///
+/// Following lines indented by four spaces belong to the footnote, so it can contain several
+/// paragraphs or any other block, see `block_content`. Returns false without consuming anything
+/// if there is no definition.
+pub fn match_footnote_definition(text: &str, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, options: &ParseOptions, c: (usize, char), depth: usize) -> Result<bool, Error> {
+    let marker_end = match footnote_label_end(text, c.0) {
+        Some(end) if depth < MAX_NESTING && is_line_start(text, c.0) && text[end..].starts_with(':') => end + 1,
+        _ => return Ok(false),
+    };
+    let end = line_end(text, marker_end);
+    let spaces = text[marker_end..end].bytes().take_while(|b| *b == b' ').count();
+    tokens.push(Token::new(TokenType::FootnoteDefinitionBegin, c.0, marker_end));
+    let (content, offsets, end, _) = block_content(text, marker_end + spaces, end, 4);
+    tokens.append(&mut lex_block(text, &content, &offsets, 0, options, depth)?);
+    tokens.push(Token::new(TokenType::FootnoteDefinitionEnd, end, end));
+    advance_to(iter, end);
+
+    Ok(true)
+}
+
+/// Matches a `[^label]` footnote reference.
+/// Returns false without consuming anything if there is no reference.
+pub fn match_footnote_reference(text: &str, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, c: (usize, char)) -> bool {
+    match footnote_label_end(text, c.0) {
+        Some(end) => {
+            tokens.push(Token::new(TokenType::FootnoteReference, c.0, end));
+            advance_to(iter, end);
+            true
+        },
+        None => false,
+    }
+}
+
+/// Returns the byte offset after the `]` of a `[^label]` starting at `begin`.
+/// The label can't be empty or contain whitespace.
+fn footnote_label_end(text: &str, begin: usize) -> Option<usize> {
+    let rest = text[begin..].strip_prefix("[^")?;
+    let length = rest.find(|c: char| c == ']' || c == '[' || c.is_whitespace())?;
+    if length == 0 || !rest[length..].starts_with(']') {
+        return None;
+    }
+
+    Some(begin + 2 + length + 1)
+}
+
+pub fn match_link(text: &str, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, c: (usize, char)) {
+    if is_line_start(text, c.0) {
+        let end = line_end(text, c.0);
+        if reference::definition(text, c.0, end).is_some() {
+            tokens.push(Token::new(TokenType::LinkDefinition, c.0, end));
+            advance_to(iter, end);
+            return;
+        }
+    }
+
     let text_begin: usize = c.0 + 1;
     loop {
+        // The newline is left for the caller, so a list item or paragraph can end there.
+        if let Some(&(i, '\n')) = iter.peek() {
+            tokens.push(Token::new(TokenType::Text, c.0, i));
+            break;
+        }
         match iter.next() {
             Some(v) => {
-                match v.1 {
-                    ']' =>  {
-                        let text_end: usize = v.0;
-                        match iter.peek() {
-                            Some(v) => {
+                if v.1 == ']' {
+                    let text_end: usize = v.0;
+                    let next = iter.peek().copied();
+                    let destination = match next {
+                        Some((i, '(')) => link_destination(text, i + 1),
+                        _ => None,
+                    };
+                    match (next, destination) {
+                        (_, Some((href, title, end))) => {
+                            tokens.push(Token::new(TokenType::LinkHref, href.begin, href.end));
+                            if let Some(title) = title {
+                                tokens.push(Token::new(TokenType::LinkTitle, title.begin, title.end));
+                            }
+                            tokens.push(Token::new(TokenType::LinkText, text_begin, text_end));
+                            advance_to(iter, end);
+                        },
+                        // Not a valid destination, everything up to the parenthesis is the url.
+                        (Some((i, '(')), None) => {
+                            let href_begin: usize = i + 1;
+                            while let Some(v) = iter.next() {
                                 match v.1 {
-                                    '(' => {
-                                        let href_begin: usize = v.0 + 1;
-                                        while let Some(v) = iter.next() {
-                                            match v.1 {
-                                                ')' =>  {
-                                                    tokens.push(Token::new(TokenType::LinkHref, href_begin, v.0));
-                                                    tokens.push(Token::new(TokenType::LinkText, text_begin, text_end));
-                                                    break;
-                                                },
-                                                '\n' => {
-                                                    tokens.push(Token::new(TokenType::Error, c.0, v.0));
-                                                    tokens.push(Token::new_single(TokenType::Newline, v.0));
-                                                    break;
-                                                },
-                                                _ => (),
-                                            }
-                                        }
+                                    ')' =>  {
+                                        tokens.push(Token::new(TokenType::LinkHref, href_begin, v.0));
+                                        tokens.push(Token::new(TokenType::LinkText, text_begin, text_end));
+                                        break;
                                     },
-                                    _ => tokens.push(Token::new(TokenType::Text, c.0, v.0)),
+                                    '\n' => {
+                                        tokens.push(Token::new(TokenType::Error, c.0, v.0));
+                                        tokens.push(Token::new_single(TokenType::Newline, v.0));
+                                        break;
+                                    },
+                                    _ => (),
                                 }
-                            },
-                            None => (),
-                        }
-                        break;
-                    },
-                    '\n' => {
-                        tokens.push(Token::new(TokenType::Text, c.0, v.0));
-                        tokens.push(Token::new_single(TokenType::Newline, v.0));
-                        break;
-                    },
-                    _ => (),
+                            }
+                        },
+                        _ if text_begin == text_end => tokens.push(Token::new(TokenType::Text, c.0, v.0 + 1)),
+                        _ => {
+                            // `[text][label]` and `[label][]` are full and collapsed references. Without a second
+                            // pair of brackets it is a shortcut reference, which stays text if the label isn't defined.
+                            let end = match next {
+                                Some((i, '[')) => reference_end(text, i),
+                                _ => None,
+                            }.unwrap_or(v.0 + 1);
+                            tokens.push(Token::new(TokenType::LinkText, text_begin, text_end));
+                            tokens.push(Token::new(TokenType::LinkReference, v.0 + 1, end));
+                            advance_to(iter, end);
+                        },
+                    }
+                    break;
                 }
             },
             None => {
-                tokens.push(Token::new_single(TokenType::Text, c.0));
+                tokens.push(Token::new(TokenType::Text, c.0, text.len()));
                 break;
             }
         }
     }
 }
 
-pub fn match_horizontalrule(text: &String, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, c: (usize, char)) {
+/// Parses the destination of a link or image starting after its `(`.
+///
+/// The url may be wrapped in `<>` to contain spaces, otherwise parentheses inside of it
+/// have to be balanced. It can be followed by a title in `"`, `'` or parentheses.
+/// Returns the url, the title without its quotes and the byte offset after the closing `)`.
+fn link_destination(text: &str, begin: usize) -> Option<(Span, Option<Span>, usize)> {
+    let line = &text[begin..line_end(text, begin)];
+    let mut i = line.len() - line.trim_start().len();
+    let href = if line[i..].starts_with('<') {
+        let close = line[i + 1..].find(['>', '<'])? + i + 1;
+        if !line[close..].starts_with('>') {
+            return None;
+        }
+        let href = Span::new(begin + i + 1, begin + close);
+        i = close + 1;
+        href
+    } else {
+        let start = i;
+        let mut depth: usize = 0;
+        for (j, c) in line[start..].char_indices() {
+            i = start + j;
+            match c {
+                '(' => depth += 1,
+                ')' if depth == 0 => break,
+                ')' => depth -= 1,
+                c if c.is_whitespace() => break,
+                _ => (),
+            }
+            i += c.len_utf8();
+        }
+        Span::new(begin + start, begin + i)
+    };
+
+    let after = i;
+    i += line[i..].len() - line[i..].trim_start().len();
+    let mut title = None;
+    if let Some(open) = line[i..].chars().next().filter(|c| matches!(c, '"'|'\''|'(')) {
+        let close = if open == '(' { ')' } else { open };
+        let length = find_unescaped(&line[i + 1..], close)?;
+        if i == after {
+            // The title has to be separated from the url.
+            return None;
+        }
+        title = Some(Span::new(begin + i + 1, begin + i + 1 + length));
+        i += length + 2;
+        i += line[i..].len() - line[i..].trim_start().len();
+    }
+    if line[i..].starts_with(')') {
+        Some((href, title, begin + i + 1))
+    } else {
+        None
+    }
+}
+
+/// Returns the byte offset of the first `c` in `text` that isn't escaped with a backslash.
+pub fn find_unescaped(text: &str, c: char) -> Option<usize> {
+    let mut escaped = false;
+    for (i, t) in text.char_indices() {
+        if t == c && !escaped {
+            return Some(i);
+        }
+        escaped = t == '\\' && !escaped;
+    }
+
+    None
+}
+
+/// Returns the byte offset after the `]` closing the reference label starting at `begin`,
+/// if it is closed on the same line.
+fn reference_end(text: &str, begin: usize) -> Option<usize> {
+    let end = text[begin + 1..].find([']', '[', '\n'])? + begin + 1;
+    if text[end..].starts_with(']') { Some(end + 1) } else { None }
+}
+
+pub fn match_horizontalrule(text: &str, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, c: (usize, char)) {
     iter.next();
     match iter.next() {
         Some(v) => {
@@ -170,67 +372,113 @@ pub fn match_horizontalrule(text: &String, tokens: &mut Vec<Token>, iter: &mut C
                         Some(v) => {
                             match v.1 {
                                 '\n' => {
-                                    if c.0 == 0 || &text[c.0 - 1..c.0] == "\n" {
+                                    if is_line_start(text, c.0) {
                                         tokens.push(Token::new(TokenType::HorizontalRule, c.0, v.0 + 1));
                                     } else {
                                         tokens.push(Token::new(TokenType::Text, c.0, v.0));
                                         tokens.push(Token::new_single(TokenType::Newline, v.0));
                                     }
                                 },
-                                _ => tokens.push(Token::new(TokenType::Text, c.0, v.0 + 1)),
+                                _ => tokens.push(Token::new(TokenType::Text, c.0, v.0 + v.1.len_utf8())),
                             }
                             iter.next();
                         },
                         None => tokens.push(Token::new(TokenType::Text, c.0, v.0)),
                     }
                 },
-                _ => tokens.push(Token::new(TokenType::Text, c.0, v.0 + 1)),
+                _ => tokens.push(Token::new(TokenType::Text, c.0, v.0 + v.1.len_utf8())),
             }
         },
         None => tokens.push(Token::new_double(TokenType::Text, c.0)),
     }
 }
 
-pub fn match_blockquote(mut emphasis: &mut emphasis::State, text: &String, mut tokens: &mut Vec<Token>, mut iter: &mut CharsWithPosition, c: (usize, char)) {
-    if c.0 == 0 || &text[c.0 - 1..c.0] == "\n" {
-        tokens.push(Token::new_single(TokenType::BlockquoteBegin, c.0));
-        loop {
-            match iter.next() {
-                Some(v) => {
-                    match v.1 {
-                        '\n' => {
-                            match iter.peek() {
-                                Some(v) => {
-                                    match v.1 {
-                                        '\n' => {
-                                            tokens.push(Token::new(TokenType::BlockquoteEnd, c.0, v.0));
-                                            tokens.push(Token::new_single(TokenType::Newline, v.0));
-                                            break;
-                                        },
-                                        _ => tokens.push(Token::new_single(TokenType::Text, v.0)),
-                                    }
-                                    iter.next();
-                                },
-                                None => tokens.push(Token::new(TokenType::BlockquoteEnd, c.0, v.0)),
-                            }
-                        },
-                        '*'|'~'|'_' => match_emphasis(&mut emphasis, text, &mut tokens, &mut iter, v),
-                        _ => tokens.push(Token::new_single(TokenType::Text, v.0)),
-                    }
-                },
-                None => {
-                    tokens.push(Token::new(TokenType::BlockquoteEnd, c.0, iter.last()));
-                    break;
-                },
-            }
-        }
-    } else  {
+/// Matches a blockquote, every line of it starts with `>` except for lazy continuation lines.
+///
+/// The content of the quote is lexed on its own like the content of a list item,
+/// so it can contain any block including another blockquote. A first line like `[!NOTE]`
+/// naming one of the alerts of `options` is not part of the content, it becomes a `BlockquoteAlert` token.
+pub fn match_blockquote(text: &str, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, options: &ParseOptions, c: (usize, char), depth: usize) -> Result<(), Error> {
+    if depth >= MAX_NESTING || !is_line_start(text, c.0) {
         tokens.push(Token::new_single(TokenType::Text, c.0));
+        return Ok(());
+    }
+
+    tokens.push(Token::new_single(TokenType::BlockquoteBegin, c.0));
+    let (content, offsets, end) = quote_content(text, c.0);
+    let first_line = &content[..content.find('\n').unwrap_or(content.len())];
+    let skip = match alert_marker(first_line, options) {
+        Some((begin, end)) => {
+            tokens.push(Token::new(TokenType::BlockquoteAlert, offsets[begin], offsets[end - 1] + 1));
+            first_line.len() + 1
+        },
+        None => 0,
+    };
+    tokens.append(&mut lex_block(text, &content, &offsets, skip, options, depth)?);
+    tokens.push(Token::new(TokenType::BlockquoteEnd, c.0, end));
+    advance_to(iter, end);
+
+    Ok(())
+}
+
+/// Returns the byte range of a `[!NAME]` marker making up the whole line,
+/// if `NAME` is one of the alerts of `options`.
+fn alert_marker(line: &str, options: &ParseOptions) -> Option<(usize, usize)> {
+    let marker = line.trim();
+    let name = marker.strip_prefix("[!")?.strip_suffix(']')?;
+    options.alert(name)?;
+    let begin = line.len() - line.trim_start().len();
+
+    Some((begin, begin + marker.len()))
+}
+
+/// Collects the content of a blockquote starting with the `>` at `begin`.
+///
+/// Following lines belong to the quote if they start with `>` as well. A line without it continues
+/// the quote only if it directly follows text and doesn't start another block.
+/// The `>` and a single space after it are removed from every line.
+///
+/// Returns the content, the offset in `text` of each of its bytes and the end of the quote.
+fn quote_content(text: &str, begin: usize) -> (String, Vec<usize>, usize) {
+    let mut content = String::new();
+    let mut offsets: Vec<usize> = Vec::new();
+    let mut end = begin;
+    let mut lazy = false;
+    let mut line_begin = begin;
+    loop {
+        let line_end = line_end(text, line_begin);
+        let line = &text[line_begin..line_end];
+        let indent = line.bytes().take_while(|b| *b == b' ').count();
+        let rest = &line[indent..];
+        let strip = if indent < 4 && rest.starts_with('>') {
+            indent + 1 + usize::from(rest[1..].starts_with(' '))
+        } else if lazy && !rest.trim().is_empty() && is_lazy_line(rest) {
+            indent
+        } else {
+            break;
+        };
+        if line_begin != begin {
+            content.push('\n');
+            offsets.push(end);
+        }
+        push_line(&mut content, &mut offsets, text, line_begin + strip, line_end);
+        lazy = !text[line_begin + strip..line_end].trim().is_empty();
+        end = line_end;
+        if line_end >= text.len() {
+            break;
+        }
+        line_begin = line_end + 1;
     }
+    // The content ends in a newline like any other line, the token for it is dropped again in `lex_block`.
+    content.push('\n');
+    offsets.push(end);
+    offsets.push(end);
+
+    (content, offsets, end)
 }
 
-pub fn match_code(text: &String, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, c: (usize, char)) {
-    if c.0 == 0 || &text[c.0 - 1..c.0] != "`" {
+pub fn match_code(text: &str, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, c: (usize, char)) {
+    if c.0 == 0 || text.as_bytes()[c.0 - 1] != b'`' {
         loop {
             match iter.next() {
                 Some(v) => {
@@ -253,50 +501,121 @@ pub fn match_code(text: &String, tokens: &mut Vec<Token>, iter: &mut CharsWithPo
     }
 }
 
-pub fn match_codeblock(text: &String, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, c: (usize, char)) {
-    if c.0 == 0 || &text[c.0 - 1..c.0] == "\n" {
-        iter.next();
-        match iter.peek() {
-            Some(v) => {
-                // ".unwrap()" Is safe here because peek already matched "Some()" value.
-                let v = iter.next().unwrap();
-                match v.1 {
-                    '`' =>{
-                        tokens.push(Token::new(TokenType::CodeBlockBegin, c.0, iter.index()));
-                        let lang_begin: usize = iter.index();
-                        loop {
-                            match iter.next() {
-                                Some(v) => {
-                                    match v.1 {
-                                        '\n' => {
-                                            tokens.push(Token::new(TokenType::CodeBlockLanguage, lang_begin, iter.index()));
-                                            break;
-                                        },
-                                        _ => (),
-                                    }
-                                },
-                                None => return,
-                            }
-                        }
-                        let lang_end: usize = iter.index();
-                        let lang = &text[lang_begin..lang_end - 1];
-                        let syntax = syntax::load_language_file(lang);
-                        match syntax {
-                            Some(s) => syntax::highlight_language(s, lang, lang_end, text, tokens, iter, c),
-                            None => syntax::highlight_generic(lang_end, tokens, iter, c),
-                        }
-                    },
-                    _ => tokens.push(Token::new(TokenType::Text, c.0, v.0)),
-                }
+/// Matches a fenced codeblock, the fence is three or more backticks or tildes indented by up to three spaces.
+///
+/// The info string following the opening fence is split into the language and its attributes,
+/// see `info_attributes`. The block ends with a fence of the same char which is at least as long
+/// as the opening one, or with the end of the text. Returns false without consuming anything
+/// if there is no opening fence.
+pub fn match_codeblock(text: &str, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, c: (usize, char)) -> Result<bool, Error> {
+    let line_begin = text[..c.0].rfind('\n').map_or(0, |i| i + 1);
+    let indent = c.0 - line_begin;
+    let fence = text[c.0..].chars().take_while(|f| *f == c.1).count();
+    let info_end = line_end(text, c.0 + fence);
+    let info = &text[c.0 + fence..info_end];
+    if indent > 3 || !text[line_begin..c.0].bytes().all(|b| b == b' ') || fence < 3 || (c.1 == '`' && info.contains('`')) {
+        return Ok(false);
+    }
+
+    tokens.push(Token::new(TokenType::CodeBlockBegin, c.0, c.0 + fence));
+    let language_begin = c.0 + fence + (info.len() - info.trim_start().len());
+    let language_end = text[language_begin..info_end].find(|l: char| l.is_whitespace() || l == '{').map_or(info_end, |i| language_begin + i);
+    tokens.push(Token::new(TokenType::CodeBlockLanguage, language_begin, language_end));
+    for attribute in info_attributes(text, language_end, info_end) {
+        tokens.push(Token::new(TokenType::CodeBlockAttribute, attribute.begin, attribute.end));
+    }
+
+    let begin = (info_end + 1).min(text.len());
+    let (end, close) = closing_fence(text, begin, c.1, fence);
+    let language = &text[language_begin..language_end];
+    let (code, offsets) = unindented_code(text, begin, end, indent);
+    let highlighted = match syntax::load_language_file(language)? {
+        Some(s) => syntax::highlight_language(s, language, &code),
+        None => syntax::highlight_generic(&code),
+    };
+    for t in highlighted {
+        // A token spanning several lines is split where the indentation was removed.
+        let mut piece = t.begin;
+        for i in t.begin + 1..t.end {
+            if offsets[i] != offsets[i - 1] + 1 {
+                tokens.push(Token::new(t.id, offsets[piece], offsets[i - 1] + 1));
+                piece = i;
+            }
+        }
+        if t.end > t.begin {
+            tokens.push(Token::new(t.id, offsets[piece], offsets[t.end - 1] + 1));
+        }
+    }
+    tokens.push(Token::new(TokenType::CodeBlockEnd, close.begin, close.end));
+    advance_to(iter, close.end);
+
+    Ok(true)
+}
+
+/// Returns the code `text[begin..end]` of a codeblock with up to `indent` spaces removed from the start of each line,
+/// like the indentation of its opening fence, and the offset in `text` of each of its bytes.
+fn unindented_code(text: &str, begin: usize, end: usize, indent: usize) -> (String, Vec<usize>) {
+    let mut code = String::new();
+    let mut offsets: Vec<usize> = Vec::new();
+    let mut line_begin = begin;
+    while line_begin < end {
+        let next = (line_end(text, line_begin) + 1).min(end);
+        let spaces = text[line_begin..next].bytes().take(indent).take_while(|b| *b == b' ').count();
+        push_line(&mut code, &mut offsets, text, line_begin + spaces, next);
+        line_begin = next;
+    }
+
+    (code, offsets)
+}
+
+/// Returns the spans of the attributes in the info string `text[begin..end]` following the language.
+///
+/// Attributes are separated by whitespace or commas and can be wrapped in `{}`, like `rust {linenos=true title="main.rs"}`
+/// or `rust {linenos,linenostart=5} {3,5-7}`. A value can be quoted to contain whitespace, commas or braces.
+fn info_attributes(text: &str, begin: usize, end: usize) -> Vec<Span> {
+    let mut attributes: Vec<Span> = Vec::new();
+    let mut attribute_begin = None;
+    let mut quoted = false;
+    for (i, c) in text[begin..end].char_indices() {
+        let separator = !quoted && (c.is_whitespace() || c == ',' || c == '{' || c == '}');
+        match attribute_begin {
+            Some(b) if separator => {
+                attributes.push(Span::new(begin + b, begin + i));
+                attribute_begin = None;
             },
-            None => tokens.push(Token::new(TokenType::Text, c.0, iter.index())),
+            None if !separator => attribute_begin = Some(i),
+            _ => (),
         }
-    } else {
-        tokens.push(Token::new_single(TokenType::Text, c.0));
+        if c == '"' {
+            quoted = !quoted;
+        }
+    }
+    if let Some(b) = attribute_begin {
+        attributes.push(Span::new(begin + b, end));
     }
+
+    attributes
+}
+
+/// Returns the end of the code of a codeblock starting at `begin` and the span of its closing fence.
+/// Without a closing fence the code runs to the end of the text and the span is empty.
+fn closing_fence(text: &str, begin: usize, fence_char: char, fence: usize) -> (usize, Span) {
+    let mut line_begin = begin;
+    while line_begin < text.len() {
+        let end = line_end(text, line_begin);
+        let line = &text[line_begin..end];
+        let indent = line.bytes().take_while(|b| *b == b' ').count();
+        let length = line[indent..].chars().take_while(|f| *f == fence_char).count();
+        if indent <= 3 && length >= fence && line[indent + length..].trim().is_empty() {
+            return (line_begin, Span::new(line_begin + indent, end));
+        }
+        line_begin = end + 1;
+    }
+
+    (text.len(), Span::new(text.len(), text.len()))
 }
 
-pub fn match_indentblock(text: &String, tokens: &mut Vec<Token>, mut iter: &mut CharsWithPosition, c: (usize, char)) {
+pub fn match_indentblock(text: &str, tokens: &mut Vec<Token>, mut iter: &mut CharsWithPosition, c: (usize, char)) {
     iter.next();
     if match_string("  ", &mut iter) {
         if let Some(v) = iter.peek() {
@@ -331,7 +650,7 @@ pub fn match_indentblock(text: &String, tokens: &mut Vec<Token>, mut iter: &mut
     }
 }
 
-pub fn match_emphasis(mut emphasis: &mut emphasis::State, text: &String, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, c: (usize, char)) {
+pub fn match_emphasis(emphasis: &mut emphasis::State, text: &str, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, c: (usize, char)) {
     match c.1 {
         '*' => {
             match iter.peek() {
@@ -405,7 +724,7 @@ pub fn match_emphasis(mut emphasis: &mut emphasis::State, text: &String, tokens:
     }
 }
 
-pub fn match_table(table: &table::State, text: &String, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, c: (usize, char)) -> bool {
+pub fn match_table(table: &table::State, text: &str, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, c: (usize, char)) -> bool {
     // TODO verify index positions for errors and others 
     let mut index_start = c.0 + 2;
     let mut index_end = index_start + 4;
@@ -441,7 +760,7 @@ pub fn match_table(table: &table::State, text: &String, tokens: &mut Vec<Token>,
                                                 break;
                                             },
                                             _ => {
-                                                tokens.push(Token::new_single(TokenType::Error, iter.index()));
+                                                tokens.push(Token::new_char(TokenType::Error, v));
                                                 return false;
                                             },
                                         }
@@ -516,255 +835,389 @@ pub fn match_table(table: &table::State, text: &String, tokens: &mut Vec<Token>,
     true
 }
 
-pub fn match_list(list_type: wrapper::ListType, text: &String, mut tokens: &mut Vec<Token>, mut iter: &mut CharsWithPosition, c: (usize, char)) {
-    let mut lists: Vec<wrapper::List> = Vec::with_capacity(10);
-    tokens.push(Token::new_double(list_type.0, iter.index()));
-    lists.push(wrapper::List(list_type.1, 0));
-    iter.next();
-    tokens.push(Token::new_single(TokenType::ListItemBegin, iter.index()));
-    let mut emphasis = emphasis::State::new();
-    while let Some(v) = iter.next() {
-        match v.1 {
-            '\n' => {
-                tokens.push(Token::new_single(TokenType::ListItemEnd, iter.index()));
-                if let Some(v) = iter.peek() {
-                    let indent_begin = v.0;
-                    match v.1 {
-                        '\n' => {
-                            for i in (0..lists.len()).rev() {
-                                let l = lists.pop().unwrap();
-                                tokens.push(Token::new_single(l.0, iter.index()));
-                            }
-                            break;
-                        },
-                        '*' => {
-                            iter.next();
-                            if let Some(v) = iter.peek() {
-                                match v.1 {
-                                    ' ' => {
-                                        push_list(
-                                            wrapper::ListType(TokenType::UnorderedListBegin, TokenType::UnorderedListEnd),
-                                            &mut lists,
-                                            tokens, 
-                                            iter
-                                        );
-                                    },
-                                    _ => {
-                                        tokens.push(Token::new_double(TokenType::Error, iter.last()));
-                                        iter.next();
-                                    },
-                                }
-                            }
-                        },
-                        '1' => {
-                            iter.next();
-                            if let Some(v) = iter.peek() {
-                                match v.1 {
-                                    '.' => {
-                                        iter.next();
-                                        if let Some(v) = iter.peek() {
-                                            match v.1 {
-                                                ' ' => {
-                                                    push_list(
-                                                        wrapper::ListType(TokenType::OrderedListBegin, TokenType::OrderedListEnd),
-                                                        &mut lists,
-                                                        tokens, 
-                                                        iter
-                                                    );
-                                                },
-                                                _ => {
-                                                    tokens.push(Token::new_double(TokenType::Error, iter.last()));
-                                                    iter.next();
-                                                },
-                                            }
-                                        }
-                                    },
-                                    _ => {
-                                        tokens.push(Token::new_double(TokenType::Error, iter.last()));
-                                        iter.next();
-                                    },
-                                }
-                            }
-                        },
-                        ' ' => {
-                            loop {
-                                if !match_string("    ", &mut iter) {
-                                    tokens.push(Token::new(TokenType::Error, indent_begin, iter.index()));
-                                    break;
-                                } else {
-                                    if let Some(v) = iter.peek() {
-                                        match v.1 {
-                                            ' ' => continue,
-                                            '*' => {
-                                                let current_indent = v.0 - indent_begin;
-                                                iter.next();
-                                                if let Some(v) = iter.peek() {
-                                                    match v.1 {
-                                                        ' ' => {
-                                                            push_indented_list(
-                                                                current_indent,
-                                                                wrapper::ListType(TokenType::UnorderedListBegin, TokenType::UnorderedListEnd),
-                                                                &mut lists,
-                                                                tokens, 
-                                                                iter
-                                                            );
-                                                            break;
-                                                        },
-                                                        _ => {
-                                                            tokens.push(Token::new_double(TokenType::Error, iter.last()));
-                                                            iter.next();
-                                                            break;
-                                                        },
-                                                    }
-                                                }
-                                            },
-                                            '1' => {
-                                                let current_indent = v.0 - indent_begin;
-                                                iter.next();
-                                                if let Some(v) = iter.peek() {
-                                                    match v.1 {
-                                                        '.' => {
-                                                            iter.next();
-                                                            if let Some(v) = iter.peek() {
-                                                                match v.1 {
-                                                                    ' ' => {
-                                                                        push_indented_list(
-                                                                            current_indent,
-                                                                            wrapper::ListType(TokenType::OrderedListBegin, TokenType::OrderedListEnd),
-                                                                            &mut lists,
-                                                                            tokens, 
-                                                                            iter
-                                                                        );
-                                                                        break;
-                                                                    },
-                                                                    _ => {
-                                                                        tokens.push(Token::new_double(TokenType::Error, iter.last()));
-                                                                        iter.next();
-                                                                        break;
-                                                                    },
-                                                                }
-                                                            }
-                                                        },
-                                                        _ => {
-                                                            tokens.push(Token::new_double(TokenType::Error, iter.last()));
-                                                            iter.next();
-                                                        },
-                                                    }
-                                                }
-                                            },
-                                            _ => tokens.push(Token::new(TokenType::Error, indent_begin, v.0)),
-                                        }
-                                    }
-                                }
-                            }
-                        },
-                        _ => {
-                            tokens.push(Token::new_single(TokenType::Error, iter.index()));
-                            iter.next();
-                        },
-                    }
-                } else {
-                    tokens.push(Token::new_single(TokenType::Newline, iter.last()));
-                }
+/// Returns the list marker at the start of `text` if it is followed by a space.
+///
+/// Unordered items use `*`, `-` or `+`, ordered items up to nine digits followed by `.` or `)`.
+pub fn list_marker(text: &str) -> Option<wrapper::ListMarker> {
+    let bytes = text.as_bytes();
+    let digits = bytes.iter().take_while(|b| b.is_ascii_digit()).count();
+    let marker = if digits == 0 {
+        match bytes.first() {
+            Some(&b) if b == b'*' || b == b'-' || b == b'+' => wrapper::ListMarker {
+                list_type: wrapper::ListType(TokenType::UnorderedListBegin, TokenType::UnorderedListEnd),
+                number: None,
+                length: 1,
+                width: 1,
+                delimiter: b as char,
             },
-            '*'|'~'|'_' => match_emphasis(&mut emphasis, text, &mut tokens, &mut iter, v),
-            '[' => match_link(text, &mut tokens, &mut iter, v),
-            '-' => match_checkbutton(text, &mut tokens, &mut iter, v),
-            _ => tokens.push(Token::new_single(TokenType::Text, iter.last())), // TODO why is last() the correct thing to do here?
+            _ => return None,
         }
+    } else if digits <= 9 && matches!(bytes.get(digits), Some(b'.')|Some(b')')) {
+        wrapper::ListMarker {
+            list_type: wrapper::ListType(TokenType::OrderedListBegin, TokenType::OrderedListEnd),
+            number: text[..digits].parse().ok(),
+            length: digits,
+            width: digits + 1,
+            delimiter: bytes[digits] as char,
+        }
+    } else {
+        return None;
+    };
+
+    match bytes.get(marker.width) {
+        Some(b' ') => Some(marker),
+        _ => None,
     }
-    // maybe push token
 }
 
-fn push_list(list_type: wrapper::ListType, lists: &mut Vec<wrapper::List>, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition) {
-    if lists[lists.len() - 1].1 > 0 {
-        for i in (1..lists.len()).rev() {
-            if lists[i].1 != 0 || lists[i].0 != list_type.1 {
-                let l = lists.pop().unwrap();
-                tokens.push(Token::new_single(l.0, iter.index()));
-            }
+/// Returns the indentation and the list marker of a line that begins a list item.
+/// The marker can be indented by up to three spaces.
+fn line_marker(line: &str) -> Option<(usize, wrapper::ListMarker)> {
+    let indent = line.bytes().take_while(|b| *b == b' ').count();
+    if indent > 3 {
+        return None;
+    }
+
+    list_marker(&line[indent..]).map(|marker| (indent, marker))
+}
+
+/// Starts a list if the line at the current position begins with a list marker.
+///
+/// With `interrupt` set ordered lists have to start at 1, so a line
+/// that happens to begin with a number doesn't end the paragraph before it.
+/// A list that is directly followed by a list with another marker starts that list as well.
+pub fn match_list_start(text: &str, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, options: &ParseOptions, interrupt: bool, depth: usize) -> Result<bool, Error> {
+    if !options.lists || depth >= MAX_NESTING {
+        return Ok(false);
+    }
+    let mut matched = false;
+    loop {
+        let begin = iter.index();
+        match line_marker(&text[begin..]) {
+            Some((indent, marker)) if matched || !interrupt || marker.number.unwrap_or(1) == 1 => {
+                let end = match_list(marker, text, tokens, options, begin + indent, depth)?;
+                advance_to(iter, end);
+                matched = true;
+            },
+            _ => return Ok(matched),
         }
-        if lists[lists.len() - 1].0 == list_type.1 {
-            tokens.push(Token::new_single(TokenType::ListItemBegin, iter.index()));
-            iter.next();
-        } else {
-            let l = lists.pop().unwrap();
-            tokens.push(Token::new_single(l.0, iter.index()));
-            tokens.push(Token::new_double(list_type.0, iter.index()));
-            lists.push(wrapper::List(list_type.1, 0));
-            iter.next();
-            tokens.push(Token::new_single(TokenType::ListItemBegin, iter.index()));
+        if !text[iter.index()..].starts_with('\n') || line_marker(&text[iter.index() + 1..]).is_none() {
+            return Ok(true);
         }
-    } else {
-        if lists[lists.len() - 1].0 == list_type.1 {
-            tokens.push(Token::new_single(TokenType::ListItemBegin, iter.index()));
-            iter.next();
-        } else {
-            let l = lists.pop().unwrap();
-            tokens.push(Token::new_single(l.0, iter.index()));
-            tokens.push(Token::new_double(list_type.0, iter.index()));
-            lists.push(wrapper::List(list_type.1, 0));
-            iter.next();
-            tokens.push(Token::new_single(TokenType::ListItemBegin, iter.index()));
+        tokens.push(Token::new_single(TokenType::Newline, iter.index()));
+        iter.next();
+    }
+}
+
+fn advance_to(iter: &mut CharsWithPosition, index: usize) {
+    while iter.index() < index && iter.next().is_some() {}
+}
+
+/// Creates the list begin token for the marker at the byte offset `begin`.
+/// The token spans the bullet or the number, so the number of the first item is kept.
+fn list_begin(marker: &wrapper::ListMarker, begin: usize) -> Token {
+    Token::new(marker.list_type.0, begin, begin + marker.length)
+}
+
+/// Checks whether an item with the marker `next` continues the list of `marker`.
+///
+/// Items only belong to the same list if their markers use the same bullet
+/// or the same delimiter after the number, any other marker starts a new list.
+fn same_list(marker: &wrapper::ListMarker, next: &wrapper::ListMarker) -> bool {
+    marker.list_type.1 == next.list_type.1 && marker.delimiter == next.delimiter
+}
+
+/// Lexes the list whose first marker is at the byte offset `begin`.
+/// Returns the byte offset of the end of the last line belonging to the list.
+pub fn match_list(marker: wrapper::ListMarker, text: &str, tokens: &mut Vec<Token>, options: &ParseOptions, begin: usize, depth: usize) -> Result<usize, Error> {
+    tokens.push(list_begin(&marker, begin));
+    let mut item = (begin, marker);
+    loop {
+        let (end, next) = match_item(&item.1, text, tokens, options, item.0, depth)?;
+        match next {
+            Some(next) if same_list(&item.1, &next.1) => item = next,
+            _ => {
+                tokens.push(Token::new(item.1.list_type.1, end, end));
+                return Ok(end);
+            },
         }
     }
 }
 
-fn push_indented_list(current_indent: usize, list_type: wrapper::ListType, lists: &mut Vec<wrapper::List>, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition) {
-    if lists[lists.len() - 1].1 > current_indent {
-        while let Some(l) = lists.get(lists.len() - 1) {
-            if l.1 == current_indent {
-                break;
-            }
-            let l = lists.pop().unwrap();
-            tokens.push(Token::new_single(l.0, iter.index()));
+/// Lexes a single list item whose marker is at the byte offset `begin`.
+///
+/// Following lines indented at least as far as the text after the marker belong to the item,
+/// see `block_content`. The content of the item is lexed on its own with the indentation removed,
+/// so any block can be nested inside of it.
+///
+/// Returns the byte offset of the end of the item and the marker of the next item, if one follows.
+fn match_item(marker: &wrapper::ListMarker, text: &str, tokens: &mut Vec<Token>, options: &ParseOptions, begin: usize, depth: usize) -> Result<(usize, Option<(usize, wrapper::ListMarker)>), Error> {
+    let line_begin = text[..begin].rfind('\n').map_or(0, |i| i + 1);
+    let after = begin + marker.width;
+    let end = line_end(text, after);
+    let mut spaces = text[after..end].bytes().take_while(|b| *b == b' ').count();
+    if spaces > 4 || after + spaces == end {
+        spaces = 1;
+    }
+    let indent = begin - line_begin + marker.width + spaces;
+    tokens.push(Token::new_single(TokenType::ListItemBegin, after));
+
+    let (content, offsets, end, next_line) = block_content(text, after + spaces, end, indent);
+    let next = next_line.and_then(|begin| line_marker(&text[begin..]).map(|(i, m)| (begin + i, m)));
+
+    let mut task = None;
+    if options.checkbuttons {
+        task = task_marker(&content).map(|checked| Token::new(TokenType::Checkbutton(checked), offsets[0], offsets[0] + 3));
+    }
+    let skip = if task.is_some() { 4 } else { 0 };
+    let mut item = lex_block(text, &content, &offsets, skip, options, depth)?;
+    if let Some(task) = task {
+        // The checkbutton is part of the item's first paragraph.
+        match item.first_mut() {
+            Some(t) if t.id == TokenType::ParagraphBegin => {
+                t.begin = task.begin;
+                t.end = task.begin;
+                item.insert(1, Token::new(TokenType::Text, task.end, task.end + 1));
+                item.insert(1, task);
+            },
+            _ => item.insert(0, task),
         }
-        if lists[lists.len() - 1].0 == list_type.1 {
-            tokens.push(Token::new_single(TokenType::ListItemBegin, iter.index()));
-            iter.next();
+    }
+    tokens.append(&mut item);
+    tokens.push(Token::new(TokenType::ListItemEnd, end, end));
+
+    Ok((end, next))
+}
+
+/// Collects the content of a container block like a list item, whose first line is `text[begin..end]`.
+///
+/// Following lines belong to the block if they are indented by at least `indent` spaces,
+/// blank lines included as long as more indented lines follow. A line that isn't indented enough
+/// continues the block only if it directly follows the block's text and doesn't start another block.
+///
+/// Returns the content with the indentation removed, the offset in `text` of each of its bytes,
+/// the end of the block and the beginning of the line that ended it, if one did.
+fn block_content(text: &str, begin: usize, end: usize, indent: usize) -> (String, Vec<usize>, usize, Option<usize>) {
+    let mut content = String::new();
+    let mut offsets: Vec<usize> = Vec::new();
+    push_line(&mut content, &mut offsets, text, begin, end);
+    let mut end = end;
+    let mut blank = false;
+    let mut next = None;
+    let mut pos = end;
+    while pos < text.len() {
+        let line_begin = pos + 1;
+        pos = line_end(text, line_begin);
+        let line = &text[line_begin..pos];
+        let line_indent = line.bytes().take_while(|b| *b == b' ').count();
+        let strip = if line.trim().is_empty() {
+            blank = true;
+            continue;
+        } else if line_indent >= indent {
+            indent
+        } else if !blank && is_lazy_line(&line[line_indent..]) {
+            line_indent
         } else {
-            let l = lists.pop().unwrap();
-            tokens.push(Token::new_single(l.0, iter.index()));
-            tokens.push(Token::new_double(list_type.0, iter.index()));
-            lists.push(wrapper::List(list_type.1, current_indent));
-            iter.next();
-            tokens.push(Token::new_single(TokenType::ListItemBegin, iter.index()));
+            next = Some(line_begin);
+            break;
+        };
+        for (i, _) in text[end..line_begin].match_indices('\n') {
+            content.push('\n');
+            offsets.push(end + i);
         }
-    } else if lists[lists.len() - 1].1 == current_indent {
-        if lists[lists.len() - 1].0 == list_type.1 {
-            tokens.push(Token::new_single(TokenType::ListItemBegin, iter.index()));
-            iter.next();
+        push_line(&mut content, &mut offsets, text, line_begin + strip, pos);
+        end = pos;
+        blank = false;
+    }
+    // The content ends in a newline like any other line, the token for it is dropped again in `lex_block`.
+    content.push('\n');
+    offsets.push(end);
+    offsets.push(end);
+
+    (content, offsets, end, next)
+}
+
+/// Lexes the content of a container block on its own, skipping its first `skip` bytes,
+/// and maps the tokens back to byte offsets into `text`. `depth` is the nesting of the container itself.
+fn lex_block(text: &str, content: &str, offsets: &[usize], skip: usize, options: &ParseOptions, depth: usize) -> Result<Vec<Token>, Error> {
+    let mut tokens = lexer::lex_content(&content[skip..], options, depth + 1)?;
+    if matches!(tokens.last(), Some(t) if t.id == TokenType::Newline && t.begin + skip == content.len() - 1) {
+        tokens.pop();
+    }
+
+    Ok(tokens.into_iter().map(|t| {
+        let map = |i: usize| offsets[(i + skip).min(offsets.len() - 1)];
+        let begin = map(t.begin);
+        let end = if t.end > t.begin { map(t.end - 1) + 1 } else { begin };
+        Token::new(t.id, begin, end.min(text.len()))
+    }).collect())
+}
+
+/// Returns the byte offset of the newline ending the line that contains `index`, or the length of the text.
+fn line_end(text: &str, index: usize) -> usize {
+    text[index..].find('\n').map_or(text.len(), |i| index + i)
+}
+
+/// Appends `text[begin..end]` to the content of a list item and remembers where each byte came from.
+fn push_line(content: &mut String, offsets: &mut Vec<usize>, text: &str, begin: usize, end: usize) {
+    content.push_str(&text[begin..end]);
+    offsets.extend(begin..end);
+}
+
+/// Checks whether a line that isn't indented enough to belong to a list item or footnote starts a new block.
+fn interrupts_item(line: &str) -> bool {
+    line_marker(line).is_some()
+        || footnote_label_end(line, 0).is_some_and(|end| line[end..].starts_with(':'))
+        || line.starts_with('#')
+        || line.starts_with('>')
+        || line.starts_with("```")
+        || line.starts_with("~~~")
+        || line.starts_with('|')
+        || (line.trim_end().len() >= 3 && line.trim_end().bytes().all(|b| b == b'-'))
+}
+
+/// Checks whether a line that isn't part of a container by itself can continue the container's text.
+/// A setext underline can't, otherwise it would turn text it isn't next to in the markdown into a heading.
+fn is_lazy_line(line: &str) -> bool {
+    !interrupts_item(line) && heading_underline(line).is_none()
+}
+
+/// Returns whether the task marker `[ ] ` or `[x] ` at the start of a list item is checked.
+fn task_marker(content: &str) -> Option<bool> {
+    match content.get(..4) {
+        Some("[ ] ") => Some(false),
+        Some("[x] ")|Some("[X] ") => Some(true),
+        _ => None,
+    }
+}
+
+/// Matches an autolink like `<https://example.com>` or `<someone@example.com>`.
+/// Returns false without consuming anything if the `<` doesn't start one.
+pub fn match_autolink(text: &str, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, c: (usize, char)) -> bool {
+    let end = match text[c.0 + 1..].find(['>', '<', ' ', '\t', '\n']) {
+        Some(i) if text[c.0 + 1 + i..].starts_with('>') => c.0 + 1 + i,
+        _ => return false,
+    };
+    let link = &text[c.0 + 1..end];
+    if !is_uri(link) && !is_email(link) {
+        return false;
+    }
+    tokens.push(Token::new(TokenType::Autolink, c.0 + 1, end));
+    advance_to(iter, end + 1);
+
+    true
+}
+
+/// Checks whether the text is an absolute uri, a scheme of 2 to 32 chars followed by a `:`.
+fn is_uri(text: &str) -> bool {
+    match text.find(':') {
+        Some(i) => (2..=32).contains(&i)
+            && text.starts_with(|c: char| c.is_ascii_alphabetic())
+            && text[..i].chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+'|'.'|'-')),
+        None => false,
+    }
+}
+
+/// Checks whether the text is an email address like `someone@example.com`.
+pub fn is_email(text: &str) -> bool {
+    match text.split_once('@') {
+        Some((local, domain)) => !local.is_empty()
+            && local.chars().all(|c| c.is_ascii_alphanumeric() || ".!#$%&'*+/=?^_`{|}~-".contains(c))
+            && !domain.is_empty()
+            && domain.split('.').all(|l| !l.is_empty() && !l.starts_with('-') && !l.ends_with('-')
+                && l.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')),
+        None => false,
+    }
+}
+
+/// Matches a url starting with `http://`, `https://` or `www.` in the middle of text.
+///
+/// The url ends at whitespace or a `<`. Trailing punctuation and a closing parenthesis
+/// without an opening one inside of the url are left out of it, so `(see www.example.com).` works.
+pub fn match_bare_url(text: &str, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, c: (usize, char)) {
+    match bare_url_end(text, c.0) {
+        Some(end) => {
+            tokens.push(Token::new(TokenType::BareUrl, c.0, end));
+            advance_to(iter, end);
+        },
+        None => tokens.push(Token::new_char(TokenType::Text, c)),
+    }
+}
+
+fn bare_url_end(text: &str, begin: usize) -> Option<usize> {
+    let before = text[..begin].chars().next_back();
+    if before.is_some_and(|c| !c.is_whitespace() && !matches!(c, '('|'*'|'_'|'~'|'"'|'\'')) {
+        return None;
+    }
+    let rest = &text[begin..];
+    let prefix = ["http://", "https://", "www."].iter().find(|p| rest.starts_with(*p))?;
+    let mut url = &rest[..rest.find(|c: char| c.is_whitespace() || c == '<').unwrap_or(rest.len())];
+    loop {
+        let trimmed = url.trim_end_matches(['?', '!', '.', ',', ':', ';', '*', '_', '~', '\'', '"']);
+        let trimmed = if trimmed.ends_with(')') && trimmed.matches(')').count() > trimmed.matches('(').count() {
+            &trimmed[..trimmed.len() - 1]
         } else {
-            let l = lists.pop().unwrap();
-            tokens.push(Token::new_single(l.0, iter.index()));
-            tokens.push(Token::new_double(list_type.0, iter.index()));
-            lists.push(wrapper::List(list_type.1, current_indent));
-            iter.next();
-            tokens.push(Token::new_single(TokenType::ListItemBegin, iter.index()));
+            trimmed
+        };
+        if trimmed.len() == url.len() {
+            break;
         }
-    } else {
-        tokens.push(Token::new_double(list_type.0, iter.index()));
-        lists.push(wrapper::List(list_type.1, current_indent));
-        iter.next();
-        tokens.push(Token::new_single(TokenType::ListItemBegin, iter.index()));
+        url = trimmed;
     }
+    if !url.get(prefix.len()..).is_some_and(|rest| rest.contains(|c: char| c.is_alphanumeric())) {
+        return None;
+    }
+
+    Some(begin + url.len())
 }
 
-pub fn match_html(tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, c: (usize, char)) {
-    while let Some(v) = iter.next() {
-        match v.1 {
-            '>' => {
-                if let Some(n) = iter.peek() {
-                    match n.1 {
-                        '\n' => tokens.push(Token::new(TokenType::Html, c.0, n.0)),
-                        _ => tokens.push(Token::new(TokenType::Html, c.0, v.0)),
-                    }
-                }
-                break;
-            },
-            _ => (),
-        }
+pub fn match_html(text: &str, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, c: (usize, char)) {
+    match html_length(&text[c.0..]) {
+        Some(length) => {
+            tokens.push(Token::new(TokenType::Html, c.0, c.0 + length));
+            while iter.index() < c.0 + length && iter.next().is_some() {}
+        },
+        None => tokens.push(Token::new_char(TokenType::Text, c)),
+    }
+}
+
+/// Returns the byte length of the html tag or comment at the start of `text`.
+///
+/// A `<` that isn't followed by a tag name, `/`, `!` or `?` is just text.
+/// The closing `>` has to come before the next blank line.
+fn html_length(text: &str) -> Option<usize> {
+    if let Some(comment) = text.strip_prefix("<!--") {
+        return find_in_paragraph(comment, "-->").map(|i| i + 7);
+    }
+    let rest = text[1..].strip_prefix('/').unwrap_or(&text[1..]);
+    match rest.chars().next() {
+        Some('!')|Some('?') if rest.len() == text.len() - 1 => (),
+        Some(c) if c.is_ascii_alphabetic() => {
+            let name = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '-').unwrap_or(rest.len());
+            match rest[name..].chars().next() {
+                Some(c) if c.is_whitespace() || c == '/' || c == '>' => (),
+                _ => return None,
+            }
+        },
+        _ => return None,
     }
+
+    find_in_paragraph(text, ">").map(|i| i + 1)
+}
+
+/// Returns the byte offset of the first `pattern` in `text` that comes before a blank line.
+///
+/// Stopping at the blank line keeps lexing linear when many `<` are never closed.
+fn find_in_paragraph(text: &str, pattern: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    (0..bytes.len())
+        .take_while(|&i| !bytes[i..].starts_with(b"\n\n"))
+        .find(|&i| bytes[i..].starts_with(pattern.as_bytes()))
+}
+
+/// Checks whether the byte offset `index` is at the beginning of a line.
+pub fn is_line_start(text: &str, index: usize) -> bool {
+    index == 0 || text.as_bytes()[index - 1] == b'\n'
 }
 
 pub fn match_string(query: &str, iter: &mut CharsWithPosition) -> bool {
@@ -0,0 +1,239 @@
+use crate::token::TokenType;
+
+/// Struct describing a kind of alert, a blockquote whose first line is `[!NAME]`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct AlertKind {
+    /// The name used in the markdown, compared case insensitively.
+    pub name: String,
+    /// The title rendered above the content of the alert.
+    pub title: String,
+}
+
+impl AlertKind {
+    pub fn new(name: &str, title: &str) -> AlertKind {
+        AlertKind {
+            name: name.to_string(),
+            title: title.to_string(),
+        }
+    }
+}
+
+/// Struct that enables or disables the individual markdown constructs.
+///
+/// A disabled construct is not recognized by the lexer and its markdown is output as plain text.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ParseOptions {
+    /// `# heading` and text underlined with `===` or `---`.
+    pub headings: bool,
+    /// `*italic*` and `**bold**`
+    pub emphasis: bool,
+    /// `` `code` ``
+    pub code_spans: bool,
+    /// Code fenced by three or more backticks or tildes.
+    pub code_blocks: bool,
+    /// `[text](url)`, reference links with their `[label]: url` definitions and `<url>` autolinks.
+    pub links: bool,
+    /// `![alt](src)`
+    pub images: bool,
+    /// Lines starting with a bullet or a number followed by `.` or `)`.
+    pub lists: bool,
+    /// Lines starting with `>`, alerts are blockquotes as well.
+    pub blockquotes: bool,
+    /// `__underline__`
+    pub underline: bool,
+    /// `~~strike~~`
+    pub strike: bool,
+    /// `- [ ]` and `- [x]`
+    pub checkbuttons: bool,
+    /// `| a | b |` rows with a `| --- |` delimiter row.
+    pub tables: bool,
+    /// Inline html tags and comments.
+    pub html: bool,
+    /// Lines indented by four spaces.
+    pub indent_blocks: bool,
+    /// A paragraph containing only `[TOC]`, replaced by the table of contents.
+    pub toc: bool,
+    /// Urls starting with `http://`, `https://` or `www.` in text are turned into links.
+    /// This extension is disabled by default.
+    pub bare_urls: bool,
+    /// `[^label]` references and `[^label]: text` definitions.
+    pub footnotes: bool,
+    /// The kinds of alerts recognized in blockquotes like `> [!NOTE]`, an empty list disables alerts.
+    pub alerts: Vec<AlertKind>,
+}
+
+impl ParseOptions {
+    /// Creates `ParseOptions` with every construct enabled, except for the `bare_urls` extension.
+    /// The alerts are the ones GitHub supports, `NOTE`, `TIP`, `IMPORTANT`, `WARNING` and `CAUTION`.
+    pub fn new() -> ParseOptions {
+        ParseOptions {
+            headings: true,
+            emphasis: true,
+            code_spans: true,
+            code_blocks: true,
+            links: true,
+            images: true,
+            lists: true,
+            blockquotes: true,
+            underline: true,
+            strike: true,
+            checkbuttons: true,
+            tables: true,
+            html: true,
+            indent_blocks: true,
+            toc: true,
+            bare_urls: false,
+            footnotes: true,
+            alerts: vec![
+                AlertKind::new("NOTE", "Note"),
+                AlertKind::new("TIP", "Tip"),
+                AlertKind::new("IMPORTANT", "Important"),
+                AlertKind::new("WARNING", "Warning"),
+                AlertKind::new("CAUTION", "Caution"),
+            ],
+        }
+    }
+
+    /// Returns the alert kind called `name`, ignoring case.
+    pub fn alert(&self, name: &str) -> Option<&AlertKind> {
+        self.alerts.iter().find(|a| a.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Checks whether tokens of type `id` belong to an enabled construct.
+    ///
+    /// Headings, lists, blockquotes, code blocks, links, images and code spans are only
+    /// tokenized by the lexer when enabled, so their tokens are always accepted.
+    pub fn is_enabled(&self, id: TokenType) -> bool {
+        match id {
+            TokenType::ItalicBegin|TokenType::ItalicEnd|TokenType::BoldBegin|TokenType::BoldEnd => self.emphasis,
+            TokenType::UnderlineBegin|TokenType::UnderlineEnd => self.underline,
+            TokenType::StrikeBegin|TokenType::StrikeEnd => self.strike,
+            TokenType::Checkbutton(_) => self.checkbuttons,
+            TokenType::TableBegin|TokenType::TableEnd|TokenType::TableColumnLeft
+            |TokenType::TableColumnRight|TokenType::TableColumnCenter => self.tables,
+            TokenType::Html => self.html,
+            TokenType::IndentBlock => self.indent_blocks,
+            TokenType::BareUrl => self.bare_urls,
+            TokenType::FootnoteReference|TokenType::FootnoteDefinitionBegin|TokenType::FootnoteDefinitionEnd => self.footnotes,
+            _ => true,
+        }
+    }
+}
+
+impl Default for ParseOptions {
+    fn default() -> ParseOptions {
+        ParseOptions::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::lexer;
+    use crate::parser;
+    use crate::renderer::HtmlRenderer;
+    use crate::renderer::Renderer;
+
+    #[test]
+    fn disabled_constructs() -> Result<(), Error> {
+        let none = ParseOptions {
+            headings: false,
+            emphasis: false,
+            code_spans: false,
+            code_blocks: false,
+            links: false,
+            images: false,
+            lists: false,
+            blockquotes: false,
+            underline: false,
+            strike: false,
+            checkbuttons: false,
+            tables: false,
+            html: false,
+            indent_blocks: false,
+            toc: false,
+            bare_urls: false,
+            footnotes: false,
+            alerts: Vec::new(),
+        };
+        assert_eq!(crate::to_html_with_options("__a__ ~~b~~ **c** *d*\n", &none)?, "<p>__a__ ~~b~~ **c** *d*</p>\n");
+        assert_eq!(crate::to_html_with_options("# a\n", &none)?, "<p># a</p>\n");
+        assert_eq!(crate::to_html_with_options("a\n===\n", &none)?, "<p>a\n===</p>\n");
+        assert_eq!(crate::to_html_with_options("`a`\n", &none)?, "<p>`a`</p>\n");
+        assert_eq!(crate::to_html_with_options("```\na\n```\n", &none)?, "<p>```\na\n```</p>\n");
+        assert_eq!(crate::to_html_with_options("[a](b) [c]\n\n[c]: /c\n", &none)?, "<p>[a](b) [c]</p>\n<p>[c]: /c</p>\n");
+        assert_eq!(crate::to_html_with_options("<https://a.b>\n", &none)?, "<p>&lt;https://a.b&gt;</p>\n");
+        assert_eq!(crate::to_html_with_options("![a](b)\n", &none)?, "<p>![a](b)</p>\n");
+        assert_eq!(crate::to_html_with_options("- a\n1. b\n", &none)?, "<p>- a\n1. b</p>\n");
+        assert_eq!(crate::to_html_with_options("> a\n", &none)?, "<p>&gt; a</p>\n");
+        let lists = ParseOptions { lists: true, ..none.clone() };
+        assert_eq!(crate::to_html_with_options("- [x] done\n", &lists)?, "\n<ul>\n<li>[x] done</li>\n</ul>\n");
+        assert_eq!(crate::to_html_with_options("a - [x] done\n", &none)?, "<p>a - [x] done</p>\n");
+        assert_eq!(crate::to_html_with_options("<b>a</b>\n", &none)?, "<p>&lt;b&gt;a&lt;/b&gt;</p>\n");
+        assert_eq!(crate::to_html_with_options("a\n\n    code\n", &none)?, "<p>a</p>\n<p>code</p>\n");
+        assert_eq!(crate::to_html_with_options("[TOC]\n", &none)?, "<p>[TOC]</p>\n");
+        assert_eq!(crate::to_html_with_options("a[^1]\n", &none)?, "<p>a[^1]</p>\n");
+        let blockquotes = ParseOptions { blockquotes: true, ..none.clone() };
+        assert_eq!(crate::to_html_with_options("> [!NOTE]\n", &blockquotes)?, "<blockquote>\n<p>[!NOTE]</p>\n</blockquote>\n");
+        let table = crate::to_html_with_options("a\n\n| a | b |\n| --- | --- |\n| c | d |\n\n", &none)?;
+        assert!(!table.contains("<table>"), "{}", table);
+
+        let all = ParseOptions::new();
+        assert_eq!(crate::to_html_with_options("__a__ ~~b~~ **c** *d*\n", &all)?, "<p><u>a</u> <strike>b</strike> <b>c</b> <i>d</i></p>\n");
+        assert!(crate::to_html_with_options("a\n\n| a | b |\n| --- | --- |\n| c | d |\n\n", &all)?.contains("<table>"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn bare_urls() -> Result<(), Error> {
+        let options = ParseOptions { bare_urls: true, ..ParseOptions::new() };
+        assert_eq!(crate::to_html_with_options("Visit https://example.com/path_(x), (www.example.org/docs). Or http://a.b/c?!\n", &options)?,
+            "<p>Visit <a href=\"https://example.com/path_(x)\">https://example.com/path_(x)</a>, \
+            (<a href=\"http://www.example.org/docs\">www.example.org/docs</a>). Or <a href=\"http://a.b/c\">http://a.b/c</a>?!</p>\n");
+        assert_eq!(crate::to_html_with_options("*http://a.b* wordhttp://c.d `http://e.f` www.\n", &options)?,
+            "<p><i><a href=\"http://a.b\">http://a.b</a></i> wordhttp://c.d <code>http://e.f</code> www.</p>\n");
+        assert_eq!(crate::to_html_with_options("Visit https://example.com\n", &ParseOptions::new())?, "<p>Visit https://example.com</p>\n");
+        assert_eq!(crate::to_html_with_options("https://a.b/c). [https://d.e](/x) <https://f.g> https://\n", &options)?,
+            "<p><a href=\"https://a.b/c\">https://a.b/c</a>). <a href=\"/x\">https://d.e</a> <a href=\"https://f.g\">https://f.g</a> https://</p>\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn alerts() -> Result<(), Error> {
+        let mut options = ParseOptions::new();
+        options.alerts.push(AlertKind::new("DEPRECATED", "Deprecated since 2.0"));
+        assert_eq!(crate::to_html_with_options("> [!deprecated]\n> Use `b` instead.\n", &options)?,
+            "<div class=\"alert alert-deprecated\">\n<p class=\"alert-title\">Deprecated since 2.0</p>\n<p>Use <code>b</code> instead.</p>\n</div>\n");
+        assert_eq!(crate::to_html_with_options("> [!DEPRECATED]\n", &ParseOptions::new())?, "<blockquote>\n<p>[!DEPRECATED]</p>\n</blockquote>\n");
+        assert_eq!(crate::to_html_with_options("> [!NOTE] text\n", &options)?, "<blockquote>\n<p>[!NOTE] text</p>\n</blockquote>\n");
+        assert_eq!(crate::to_html_with_options("> [!NOTE]\n", &options)?, "<div class=\"alert alert-note\">\n<p class=\"alert-title\">Note</p>\n</div>\n");
+        assert_eq!(crate::to_html_with_options("> [!note]  \n> - a\n", &options)?,
+            "<div class=\"alert alert-note\">\n<p class=\"alert-title\">Note</p>\n\n<ul>\n<li>a</li>\n</ul>\n</div>\n");
+        assert_eq!(crate::to_html_with_options("> [!UNKNOWN]\n> text\n", &options)?, "<blockquote>\n<p>[!UNKNOWN]\ntext</p>\n</blockquote>\n");
+        // Only the first line of the quote can be the marker.
+        assert_eq!(crate::to_html_with_options("> a\n> [!TIP]\n", &options)?, "<blockquote>\n<p>a\n[!TIP]</p>\n</blockquote>\n");
+        assert_eq!(crate::to_html_with_options("> [!WARNING]\n>\n> > nested\n", &options)?,
+            "<div class=\"alert alert-warning\">\n<p class=\"alert-title\">Warning</p>\n<blockquote>\n<p>nested</p>\n</blockquote>\n</div>\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn parser_fallback() -> Result<(), Error> {
+        let text = "__a__ <b> *c*";
+        let tokens = lexer::lex(text, &ParseOptions::new())?;
+        let options = ParseOptions { underline: false, html: false, emphasis: false, ..ParseOptions::new() };
+        let html = HtmlRenderer::new().render(&parser::parse(text, &tokens, &options)?);
+        assert_eq!(html, "<p>__a__ &lt;b&gt; *c*</p>\n");
+        let text = "> [!TIP]\n> a\n";
+        let tokens = lexer::lex(text, &ParseOptions::new())?;
+        let options = ParseOptions { alerts: Vec::new(), ..ParseOptions::new() };
+        let html = HtmlRenderer::new().render(&parser::parse(text, &tokens, &options)?);
+        assert_eq!(html, "<blockquote>\n<p>[!TIP]\na</p>\n</blockquote>\n");
+
+        Ok(())
+    }
+}
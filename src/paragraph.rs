@@ -0,0 +1,204 @@
+use crate::token::Token;
+use crate::token::TokenType;
+
+/// Groups the top level inline tokens into paragraphs.
+///
+/// Paragraphs are separated by blank lines and by block elements.
+/// Newlines inside a paragraph are soft breaks, unless the line ends
+/// in two or more spaces or a backslash, which turns it into a `LineBreak`.
+/// Lines starting with a checkbutton are always preceded by a `LineBreak`.
+///
+/// A paragraph followed by a `HeadingUnderline` becomes a setext heading, its `ParagraphBegin`
+/// is replaced by a `SetextHeading`. Without a paragraph the underline is a rule or text.
+pub fn insert_paragraphs(text: &str, tokens: Vec<Token>) -> Vec<Token> {
+    let mut output: Vec<Token> = Vec::with_capacity(tokens.len() + tokens.len() / 8);
+    let mut depth: usize = 0;
+    let mut in_heading = false;
+    let mut paragraph: Option<usize> = None;
+    for (i, t) in tokens.iter().enumerate() {
+        let mut id = t.id;
+        // An underline inside of a container was already handled when its content was lexed.
+        if id == TokenType::HeadingUnderline && depth == 0 {
+            if paragraph.take().is_some() {
+                if let Some(begin) = output.iter_mut().rev().find(|o| o.id == TokenType::ParagraphBegin) {
+                    begin.id = TokenType::SetextHeading;
+                }
+                output.push(Token::new(id, t.begin, t.end));
+                continue;
+            }
+            id = if &text[t.begin..t.end] == "---" { TokenType::HorizontalRule } else { TokenType::Text };
+        }
+        match id {
+            TokenType::BlockquoteBegin|TokenType::CodeBlockBegin|TokenType::TableBegin
+            |TokenType::UnorderedListBegin|TokenType::OrderedListBegin|TokenType::FootnoteDefinitionBegin => {
+                close_paragraph(&mut output, &mut paragraph);
+                depth += 1;
+            },
+            TokenType::BlockquoteEnd|TokenType::CodeBlockEnd|TokenType::TableEnd
+            |TokenType::UnorderedListEnd|TokenType::OrderedListEnd|TokenType::FootnoteDefinitionEnd => {
+                depth = depth.saturating_sub(1);
+            },
+            _ if depth > 0 => (),
+            TokenType::Heading => {
+                close_paragraph(&mut output, &mut paragraph);
+                in_heading = true;
+            },
+            TokenType::HorizontalRule|TokenType::IndentBlock|TokenType::LinkDefinition => close_paragraph(&mut output, &mut paragraph),
+            TokenType::Newline if in_heading => in_heading = false,
+            TokenType::Newline if paragraph.is_some() && matches!(tokens.get(i + 1), Some(n) if n.id == TokenType::HeadingUnderline) => (),
+            TokenType::Newline if paragraph.is_some() => {
+                if ends_paragraph(&tokens[i + 1..]) {
+                    close_paragraph(&mut output, &mut paragraph);
+                } else if let Some(begin) = hard_break(&mut output) {
+                    output.push(Token::new(TokenType::LineBreak, begin, t.end));
+                    continue;
+                } else if let Some(TokenType::Checkbutton(_)) = tokens.get(i + 1).map(|n| n.id) {
+                    // Every checkbutton of a task list stays on its own line.
+                    output.push(Token::new(TokenType::LineBreak, t.begin, t.end));
+                    continue;
+                }
+            },
+            TokenType::Newline|TokenType::Space|TokenType::Tab => (),
+            // Html on a line of its own is a block and is not wrapped in a paragraph.
+            TokenType::Html if paragraph.is_none() && ends_line(&tokens[i + 1..]) => (),
+            _ if in_heading => (),
+            _ => {
+                if paragraph.is_none() {
+                    paragraph = Some(t.begin);
+                    output.push(Token::new(TokenType::ParagraphBegin, t.begin, t.begin));
+                }
+            },
+        }
+        output.push(Token::new(id, t.begin, t.end));
+    }
+    close_paragraph(&mut output, &mut paragraph);
+
+    output
+}
+
+/// Pushes a `ParagraphEnd` spanning the whole paragraph if one is open.
+fn close_paragraph(output: &mut Vec<Token>, paragraph: &mut Option<usize>) {
+    if let Some(begin) = paragraph.take() {
+        let end = output.last().map_or(begin, |t| t.end);
+        output.push(Token::new(TokenType::ParagraphEnd, begin, end));
+    }
+}
+
+/// Checks whether the line after a newline is blank or starts a block.
+fn ends_paragraph(rest: &[Token]) -> bool {
+    for t in rest.iter() {
+        match t.id {
+            TokenType::Space|TokenType::Tab => (),
+            TokenType::Newline => return true,
+            TokenType::Heading|TokenType::HorizontalRule|TokenType::IndentBlock|TokenType::LinkDefinition
+            |TokenType::BlockquoteBegin|TokenType::CodeBlockBegin|TokenType::TableBegin
+            |TokenType::UnorderedListBegin|TokenType::OrderedListBegin|TokenType::FootnoteDefinitionBegin => return true,
+            _ => return false,
+        }
+    }
+
+    true
+}
+
+fn ends_line(rest: &[Token]) -> bool {
+    match rest.first() {
+        Some(t) => t.id == TokenType::Newline,
+        None => true,
+    }
+}
+
+/// Removes the trailing spaces or backslash marking a hard break from the output.
+/// Returns the byte offset where the break begins.
+fn hard_break(output: &mut Vec<Token>) -> Option<usize> {
+    let spaces = output.iter().rev().take_while(|t| t.id == TokenType::Space).count();
+    if spaces >= 2 {
+        let begin = output[output.len() - spaces].begin;
+        output.truncate(output.len() - spaces);
+        return Some(begin);
+    }
+    match output.last() {
+        Some(t) if spaces == 0 && t.id == TokenType::Escape => {
+            let begin = t.begin;
+            output.pop();
+            Some(begin)
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lex;
+    use crate::options::ParseOptions;
+    use crate::error::Error;
+
+    fn ids(text: &str) -> Result<Vec<TokenType>, Error> {
+        Ok(lex(text, &ParseOptions::new())?.iter().map(|t| t.id).filter(|id| !matches!(id, TokenType::Text|TokenType::Space)).collect())
+    }
+
+    #[test]
+    fn paragraphs() -> Result<(), Error> {
+        assert_eq!(ids("a\nb\n\nc")?, vec![
+            TokenType::ParagraphBegin,
+            TokenType::Newline,
+            TokenType::ParagraphEnd,
+            TokenType::Newline,
+            TokenType::Newline,
+            TokenType::ParagraphBegin,
+            TokenType::ParagraphEnd,
+        ]);
+        assert_eq!(ids("# a\nb\n\n---\n")?, vec![
+            TokenType::Heading,
+            TokenType::Newline,
+            TokenType::ParagraphBegin,
+            TokenType::ParagraphEnd,
+            TokenType::Newline,
+            TokenType::Newline,
+            TokenType::HorizontalRule,
+        ]);
+        let t = lex("one\n\ntwo", &ParseOptions::new())?;
+        let end = t.iter().rev().find(|t| t.id == TokenType::ParagraphEnd).unwrap();
+        assert_eq!((end.begin, end.end), (5, 8));
+
+        Ok(())
+    }
+
+    #[test]
+    fn setext_headings() -> Result<(), Error> {
+        assert_eq!(ids("a\nb\n===\n")?, vec![
+            TokenType::SetextHeading,
+            TokenType::Newline,
+            TokenType::Newline,
+            TokenType::HeadingUnderline,
+            TokenType::Newline,
+        ]);
+        assert_eq!(ids("# a\n---\n==\n")?, vec![
+            TokenType::Heading,
+            TokenType::Newline,
+            TokenType::HorizontalRule,
+            TokenType::Newline,
+            TokenType::ParagraphBegin,
+            TokenType::ParagraphEnd,
+            TokenType::Newline,
+        ]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn hard_breaks() -> Result<(), Error> {
+        assert_eq!(ids("a  \nb\\\nc  \n\n")?, vec![
+            TokenType::ParagraphBegin,
+            TokenType::LineBreak,
+            TokenType::LineBreak,
+            TokenType::ParagraphEnd,
+            TokenType::Newline,
+            TokenType::Newline,
+        ]);
+        let t = lex("a  \nb", &ParseOptions::new())?;
+        assert_eq!((t[2].begin, t[2].end), (1, 4));
+
+        Ok(())
+    }
+}
@@ -1,290 +1,1231 @@
-use std::fs;
-use std::io::Write;
+use std::iter::Peekable;
+use std::slice::Iter;
 
+use crate::ast::Alignment;
+use crate::ast::Document;
+use crate::ast::Emphasis;
+use crate::ast::Highlight;
+use crate::ast::Node;
+use crate::ast::Span;
+use crate::error::Error;
+use crate::footnote;
+use crate::markdown;
+use crate::options::ParseOptions;
+use crate::reference;
+use crate::slug::Slugger;
+use crate::toc::table_of_contents;
+use crate::toc::TocEntry;
 use crate::token::Token;
 use crate::token::TokenType;
-use crate::table::Column;
 
-pub fn parse(text: &String, tokens: &Vec<Token>) -> Vec<String> {
-    let mut html: Vec<String> = Vec::with_capacity(text.len());
+type Tokens<'a> = Peekable<Iter<'a, Token>>;
+
+/// Builds the document tree out of the tokens returned by the lexer.
+///
+/// Most of `options` only takes effect in the lexer. Here the tokens rejected by
+/// `ParseOptions::is_enabled` are treated as text and the `toc` and `alerts` options are applied,
+/// headings, lists, blockquotes, code blocks, links, images and code spans are always parsed.
+pub fn parse(text: &str, tokens: &[Token], options: &ParseOptions) -> Result<Document, Error> {
     let mut iter = tokens.iter().peekable();
-    while let Some(t) = iter.next() {
-        match t.id {
-            TokenType::Heading => {
-                let begin: usize = iter.next().unwrap().end;
-                let mut end: usize = begin;
-                while let Some(tok) = iter.peek() {
-                    match tok.id {
-                        TokenType::Text|TokenType::Space => {
-                            end = tok.end;
-                            iter.next();
+    let mut children = parse_blocks(text, tokens, &mut iter, options, &|_| false)?;
+    let mut diagnostics = reference::undefined(text, tokens);
+    diagnostics.append(&mut footnote::collect(&mut children));
+
+    // Custom and footnote ids are taken first, so the generated ones never collide with them.
+    let mut slugger = Slugger::new();
+    reserve_ids(&children, &mut slugger);
+    assign_ids(&mut children, &mut slugger);
+    let entries = table_of_contents(&children);
+    fill_tocs(&mut children, &entries);
+
+    Ok(Document {
+        children,
+        diagnostics,
+    })
+}
+
+/// Parses blocks until reaching a token for which `stop` returns true.
+fn parse_blocks(text: &str, tokens: &[Token], iter: &mut Tokens, options: &ParseOptions, stop: &dyn Fn(TokenType) -> bool) -> Result<Vec<Node>, Error> {
+    let mut children: Vec<Node> = Vec::new();
+    while let Some(t) = iter.peek() {
+        if stop(t.id) {
+            break;
+        } else if starts_block(t.id) {
+            if let Some(node) = parse_block(text, tokens, iter, options)? {
+                children.push(node);
+            }
+        } else if ends_block(t.id) || matches!(t.id, TokenType::Newline|TokenType::Space|TokenType::Tab) {
+            // An end without a matching begin, nothing to close.
+            // Whitespace outside of paragraphs only separates the blocks.
+            iter.next();
+        } else {
+            children.append(&mut parse_inline(text, tokens, iter, options, &|id| id == TokenType::Newline)?);
+        }
+    }
+
+    Ok(children)
+}
+
+fn starts_block(id: TokenType) -> bool {
+    matches!(id, TokenType::Heading
+        |TokenType::SetextHeading
+        |TokenType::ParagraphBegin
+        |TokenType::BlockquoteBegin
+        |TokenType::CodeBlockBegin
+        |TokenType::TableBegin
+        |TokenType::UnorderedListBegin
+        |TokenType::OrderedListBegin
+        |TokenType::HorizontalRule
+        |TokenType::IndentBlock
+        |TokenType::LinkDefinition
+        |TokenType::FootnoteDefinitionBegin)
+}
+
+fn ends_block(id: TokenType) -> bool {
+    matches!(id, TokenType::ParagraphEnd
+        |TokenType::BlockquoteEnd
+        |TokenType::CodeBlockEnd
+        |TokenType::TableEnd
+        |TokenType::UnorderedListEnd
+        |TokenType::OrderedListEnd
+        |TokenType::ListItemBegin
+        |TokenType::ListItemEnd
+        |TokenType::FootnoteDefinitionEnd)
+}
+
+fn parse_block(text: &str, tokens: &[Token], iter: &mut Tokens, options: &ParseOptions) -> Result<Option<Node>, Error> {
+    let t = match iter.next() {
+        Some(t) => t,
+        None => return Ok(None),
+    };
+    let node = match t.id {
+        TokenType::Heading => {
+            let begin: usize = expect(tokens, iter, TokenType::Space)?.end;
+            // The tokens of the heading's line, the closing `#` sequence is left out of its content.
+            let first = tokens.len() - iter.len();
+            let line_end = text[begin..].find('\n').map_or(text.len(), |i| begin + i);
+            let line = tokens[first..].iter().take_while(|n| n.id != TokenType::Newline && !ends_block(n.id) && n.begin < line_end).count();
+            let end = tokens[first..first + line].iter().fold(begin, |end, n| end.max(n.end));
+            let content_end = atx_content_end(text, begin, end);
+            let content = first + tokens[first..first + line].iter().take_while(|n| n.begin < content_end).count();
+            let mut children = parse_inline(text, &tokens[..content], &mut tokens[first..content].iter().peekable(), options, &|_| false)?;
+            let id = custom_id(&mut children);
+            for _ in 0..line {
+                iter.next();
+            }
+            skip_newline(iter);
+            Node::Heading { level: t.end - t.begin, id, children, span: Span::new(t.begin, end) }
+        },
+        TokenType::SetextHeading => {
+            let mut children = parse_inline(text, tokens, iter, options, &|id| id == TokenType::HeadingUnderline)?;
+            while matches!(children.last(), Some(Node::SoftBreak { .. })|Some(Node::LineBreak { .. })) {
+                children.pop();
+            }
+            // Without its underline the text is an ordinary paragraph.
+            let underline = match iter.next_if(|n| n.id == TokenType::HeadingUnderline) {
+                Some(underline) => underline,
+                None => {
+                    let end = children.last().map_or(t.end, |n| n.span().end);
+                    return Ok(Some(Node::Paragraph { children, span: Span::new(t.begin, end) }));
+                },
+            };
+            let id = custom_id(&mut children);
+            skip_newline(iter);
+            let level = if text[underline.begin..].starts_with('=') { 1 } else { 2 };
+            Node::Heading { level, id, children, span: Span::new(t.begin, underline.end) }
+        },
+        TokenType::ParagraphBegin => {
+            let children = parse_inline(text, tokens, iter, options, &|_| false)?;
+            let end = match iter.peek() {
+                Some(n) if n.id == TokenType::ParagraphEnd => n.end,
+                _ => children.last().map_or(t.end, |n| n.span().end),
+            };
+            skip(iter, TokenType::ParagraphEnd);
+            let toc = matches!(children.as_slice(), [Node::Text { text, .. }] if text.trim() == "[TOC]");
+            if options.toc && toc {
+                Node::Toc { entries: Vec::new(), span: Span::new(t.begin, end) }
+            } else {
+                Node::Paragraph { children, span: Span::new(t.begin, end) }
+            }
+        },
+        TokenType::BlockquoteBegin => {
+            let marker = iter.next_if(|n| n.id == TokenType::BlockquoteAlert);
+            let mut children = parse_blocks(text, tokens, iter, options, &|id| id == TokenType::BlockquoteEnd)?;
+            let end = match iter.next_if(|n| n.id == TokenType::BlockquoteEnd) {
+                Some(n) => n.end,
+                None => children.last().map_or(t.end, |n| n.span().end),
+            };
+            skip_newline(iter);
+            let span = Span::new(t.begin, end);
+            // "+ 2" and "- 1" To leave out the "[!" and "]".
+            match marker.map(|n| (n, options.alert(&text[n.begin + 2..n.end - 1]))) {
+                Some((_, Some(kind))) => Node::Alert { kind: kind.name.to_lowercase(), title: kind.title.clone(), children, span },
+                Some((n, None)) => {
+                    // An unknown marker is the first line of the quote's first paragraph.
+                    let marker = Node::Text { text: text[n.begin..n.end].to_string(), span: Span::new(n.begin, n.end) };
+                    let newline = text[n.end..].find('\n').map(|i| n.end + i);
+                    match (children.first_mut(), newline) {
+                        (Some(Node::Paragraph { children: lines, span }), Some(newline)) => {
+                            lines.splice(0..0, [marker, Node::SoftBreak { span: Span::new(newline, newline + 1) }]);
+                            span.begin = n.begin;
                         },
-                        _ => break,
+                        _ => children.insert(0, Node::Paragraph { children: vec![marker], span: Span::new(n.begin, n.end) }),
                     }
+                    Node::Blockquote { children, span }
+                },
+                None => Node::Blockquote { children, span },
+            }
+        },
+        TokenType::CodeBlockBegin => {
+            let lang: &Token = match iter.peek() {
+                Some(n) if n.id == TokenType::CodeBlockLanguage => n,
+                _ => return Ok(None),
+            };
+            iter.next();
+            let mut attributes: Vec<(String, String)> = Vec::new();
+            while let Some(n) = iter.next_if(|n| n.id == TokenType::CodeBlockAttribute) {
+                attributes.push(code_attribute(&text[n.begin..n.end]));
+            }
+            let mut children: Vec<Node> = Vec::new();
+            let mut end = lang.end;
+            while let Some(n) = iter.peek() {
+                if n.id == TokenType::CodeBlockEnd {
+                    end = n.end;
+                    iter.next();
+                    skip_newline(iter);
+                    break;
                 }
-                html.push(format!("<h{}>{}</h{}>\n", t.end - t.begin, text[begin..end].to_string(), t.end - t.begin));
-                match iter.peek() {
-                    Some(n) => {
-                        if n.id == TokenType::Newline {
-                            iter.next();
-                        }
+                match highlight(n.id) {
+                    Some(highlight) => {
+                        children.push(Node::CodeSpan { highlight, text: text[n.begin..n.end].to_string(), span: Span::new(n.begin, n.end) });
+                        end = n.end;
+                        iter.next();
                     },
-                    None => (),
+                    None => break,
                 }
-            },
-            TokenType::Checkbutton(bool) => {
-                if t.id == TokenType::Checkbutton(true) {
-                    html.push(format!("<input type=\"checkbox\" checked>"));
-                } else {
-                    html.push(format!("<input type=\"checkbox\">"));
+            }
+            let line_numbers = attributes.iter().any(|(key, value)| key == "linenos" && value != "false");
+            let first_line = attributes.iter()
+                .find(|(key, _)| key == "linenostart")
+                .and_then(|(_, value)| value.parse::<usize>().ok())
+                .unwrap_or(1);
+            let highlighted_lines = attributes.iter()
+                .filter_map(|(key, value)| match key.as_str() {
+                    "hl_lines" => line_ranges(value),
+                    _ if value.is_empty() => line_ranges(key),
+                    _ => None,
+                })
+                .flatten()
+                .collect();
+            Node::CodeBlock {
+                language: text[lang.begin..lang.end].to_string(),
+                attributes,
+                line_numbers,
+                first_line,
+                highlighted_lines,
+                children,
+                span: Span::new(t.begin, end),
+            }
+        },
+        TokenType::TableBegin if options.tables => parse_table(text, tokens, iter, options, t)?,
+        TokenType::UnorderedListBegin|TokenType::OrderedListBegin => parse_list(text, tokens, iter, options, t)?,
+        TokenType::FootnoteDefinitionBegin if options.footnotes => {
+            let children = parse_blocks(text, tokens, iter, options, &|id| id == TokenType::FootnoteDefinitionEnd)?;
+            let end = expect(tokens, iter, TokenType::FootnoteDefinitionEnd)?.end;
+            Node::FootnoteDefinition {
+                // "+ 2" and "- 2" To leave out the "[^" and "]:".
+                label: text[t.begin + 2..t.end - 2].to_string(),
+                number: 0,
+                references: 0,
+                children,
+                span: Span::new(t.begin, end),
+            }
+        },
+        TokenType::HorizontalRule => Node::HorizontalRule { span: Span::new(t.begin, t.end) },
+        TokenType::IndentBlock if !options.indent_blocks => Node::Paragraph {
+            children: vec![Node::Text { text: text[t.begin..t.end].trim().to_string(), span: Span::new(t.begin, t.end) }],
+            span: Span::new(t.begin, t.end),
+        },
+        TokenType::IndentBlock => Node::IndentBlock { text: indented_text(text, t), span: Span::new(t.begin, t.end) },
+        _ => return Ok(None),
+    };
+
+    Ok(Some(node))
+}
+
+/// Returns the byte offset where the content of an atx heading ends.
+///
+/// A closing sequence of `#` is not part of the content if it is preceded by a space
+/// or if it is all there is, so `# Title ##` and `# Title` are the same heading.
+fn atx_content_end(text: &str, begin: usize, end: usize) -> usize {
+    let content = text[begin..end].trim_end();
+    let open = content.trim_end_matches('#');
+    if open.len() == content.len() {
+        begin + content.len()
+    } else if open.is_empty() || open.ends_with(' ') || open.ends_with('\t') {
+        begin + open.trim_end().len()
+    } else {
+        begin + content.len()
+    }
+}
+
+/// Removes a trailing `{#custom-id}` from the content of a heading and returns the id.
+/// Returns an empty string if the heading has none.
+fn custom_id(children: &mut Vec<Node>) -> String {
+    let (text, span) = match children.last_mut() {
+        Some(Node::Text { text, span }) => (text, span),
+        _ => return String::new(),
+    };
+    let content = text.trim_end();
+    let open = match content.rfind("{#") {
+        Some(open) if content.ends_with('}') => open,
+        _ => return String::new(),
+    };
+    let id = &content[open + 2..content.len() - 1];
+    if id.is_empty() || id.contains(|c: char| c.is_whitespace() || c == '{' || c == '}') {
+        return String::new();
+    }
+    let id = id.to_string();
+    text.truncate(text[..open].trim_end().len());
+    span.end = span.begin + text.len();
+    if text.is_empty() {
+        children.pop();
+    }
+
+    id
+}
+
+/// Reserves the custom ids of all headings and the ids the footnotes are rendered with,
+/// so no generated id can collide with them.
+fn reserve_ids(nodes: &[Node], slugger: &mut Slugger) {
+    for node in nodes {
+        match node {
+            Node::Heading { id, .. } if !id.is_empty() => slugger.reserve(id),
+            Node::FootnoteDefinition { number, references, children, .. } => {
+                slugger.reserve(&footnote::definition_id(*number));
+                for index in 1..=*references {
+                    slugger.reserve(&footnote::reference_id(*number, index));
                 }
+                reserve_ids(children, slugger);
             },
-            TokenType::ImageAlt => {
-                html.push(format!("<img class=\"md-img\" alt=\"{}\"", text[t.begin..t.end].to_string()));
-                let t = iter.next().unwrap();
-                html.push(format!(" src=\"{}\">", text[t.begin..t.end].to_string()));
+            _ => reserve_ids(node.children(), slugger),
+        }
+    }
+}
+
+/// Gives every heading without a custom id a unique slug of its text, in document order.
+fn assign_ids(nodes: &mut [Node], slugger: &mut Slugger) {
+    for node in nodes {
+        if let Node::Heading { id, children, .. } = node {
+            if id.is_empty() {
+                *id = slugger.slug(&children.iter().map(|c| c.plain_text()).collect::<String>());
+            }
+        } else if let Some(children) = node.children_mut() {
+            assign_ids(children, slugger);
+        }
+    }
+}
+
+/// Gives every `[TOC]` placeholder the headings of the document.
+fn fill_tocs(nodes: &mut [Node], toc: &[TocEntry]) {
+    for node in nodes {
+        if let Node::Toc { entries, .. } = node {
+            *entries = toc.to_vec();
+        } else if let Some(children) = node.children_mut() {
+            fill_tocs(children, toc);
+        }
+    }
+}
+
+/// Returns the url of a link or image, the spaces a `<>` wrapped url can contain are percent encoded.
+fn link_url(text: &str, t: &Token) -> String {
+    let url = &text[t.begin..t.end];
+    if text[..t.begin].ends_with('<') && text[t.end..].starts_with('>') {
+        url.replace(' ', "%20")
+    } else {
+        url.to_string()
+    }
+}
+
+/// Returns the title of a link or image without the backslashes escaping its punctuation.
+fn link_title(title: &str) -> String {
+    let mut unescaped = String::with_capacity(title.len());
+    let mut chars = title.chars().peekable();
+    while let Some(c) = chars.next() {
+        match chars.next_if(|next| c == '\\' && next.is_ascii_punctuation()) {
+            Some(next) => unescaped.push(next),
+            None => unescaped.push(c),
+        }
+    }
+
+    unescaped
+}
+
+/// Splits a `key=value` attribute of a codeblock into the key and the value without its quotes.
+/// An attribute without a value has an empty one.
+fn code_attribute(attribute: &str) -> (String, String) {
+    match attribute.split_once('=') {
+        Some((key, value)) => {
+            let value = value.strip_prefix('"').and_then(|v| v.strip_suffix('"')).unwrap_or(value);
+            (key.to_string(), value.to_string())
+        },
+        None => (attribute.to_string(), String::new()),
+    }
+}
+
+/// Parses line ranges like `3,5-7` or `3 5-7`, returns `None` if the text contains anything else.
+fn line_ranges(text: &str) -> Option<Vec<(usize, usize)>> {
+    let mut ranges: Vec<(usize, usize)> = Vec::new();
+    for range in text.split(|c: char| c == ',' || c.is_whitespace()).filter(|r| !r.is_empty()) {
+        let (first, last) = range.split_once('-').unwrap_or((range, range));
+        let first = first.parse::<usize>().ok()?;
+        let last = last.parse::<usize>().ok()?;
+        ranges.push((first.min(last), first.max(last)));
+    }
+
+    if ranges.is_empty() { None } else { Some(ranges) }
+}
+
+/// Returns the text of an indented block without the indentation.
+/// Inside of a list item the lines are indented by the item's indentation as well.
+fn indented_text(text: &str, t: &Token) -> String {
+    let column = t.begin - text[..t.begin].rfind('\n').map_or(0, |i| i + 1);
+    text[t.begin + 4..t.end].replace(&format!("\n{}", " ".repeat(column + 4)), "\n")
+}
+
+fn parse_table(text: &str, tokens: &[Token], iter: &mut Tokens, options: &ParseOptions, t: &Token) -> Result<Node, Error> {
+    let mut alignments: Vec<Alignment> = Vec::with_capacity(15);
+    while let Some(n) = iter.peek() {
+        match n.id {
+            TokenType::TableColumnLeft => alignments.push(Alignment::Left),
+            TokenType::TableColumnRight => alignments.push(Alignment::Right),
+            TokenType::TableColumnCenter => alignments.push(Alignment::Center),
+            _ => break,
+        }
+        iter.next();
+    }
+    // The lexer inserts the columns in reverse order.
+    alignments.reverse();
+
+    let mut rows: Vec<Node> = Vec::new();
+    let mut end = t.end;
+    while let Some(n) = iter.peek() {
+        match n.id {
+            TokenType::TableEnd => {
+                end = n.end;
+                iter.next();
+                break;
             },
-            TokenType::LinkHref => {
-                html.push(format!("<a href=\"{}\">", text[t.begin..t.end].to_string()));
-                let tok = iter.next().unwrap();
-                if text[tok.begin..tok.end].len() == 0 {
-                    html.push(format!("{}</a>", text[t.begin..t.end].to_string()));
-                } else {
-                    html.push(format!("{}</a>", text[tok.begin..tok.end].to_string()));
+            TokenType::Newline => {
+                iter.next();
+            },
+            _ => {
+                let row = parse_row(text, tokens, iter, options, &alignments, rows.is_empty())?;
+                end = row.span().end;
+                rows.push(row);
+            },
+        }
+    }
+
+    Ok(Node::Table { children: rows, span: Span::new(t.begin, end) })
+}
+
+fn parse_row(text: &str, tokens: &[Token], iter: &mut Tokens, options: &ParseOptions, alignments: &[Alignment], header: bool) -> Result<Node, Error> {
+    let stop = |id: TokenType| matches!(id, TokenType::Pipe|TokenType::Newline|TokenType::TableEnd);
+    let begin = iter.peek().map_or(0, |n| n.begin);
+    let mut end = begin;
+    let mut cells: Vec<Node> = Vec::new();
+    let leading = parse_inline(text, tokens, iter, options, &stop)?;
+    if !is_blank(&leading) {
+        let leading_end = leading.last().map_or(begin, |c| c.span().end);
+        cells.push(Node::Cell { header, alignment: Alignment::Left, children: leading, span: Span::new(begin, leading_end) });
+    }
+    while let Some(n) = iter.peek() {
+        match n.id {
+            TokenType::Pipe => {
+                let pipe_end = n.end;
+                end = pipe_end;
+                iter.next();
+                let children = parse_inline(text, tokens, iter, options, &stop)?;
+                let cell_end = children.last().map_or(pipe_end, |c| c.span().end);
+                let is_cell = matches!(iter.peek(), Some(n) if n.id == TokenType::Pipe);
+                if is_cell || !is_blank(&children) {
+                    let alignment = match alignments.get(cells.len()) {
+                        Some(a) => *a,
+                        None => Alignment::Left,
+                    };
+                    cells.push(Node::Cell { header, alignment, children, span: Span::new(pipe_end, cell_end) });
                 }
             },
-            TokenType::BlockquoteBegin => {
-                html.push("<blockquote>".to_string());
-                while let Some(tok) = iter.peek() {
-                    match tok.id {
-                        TokenType::Text => html.push(text[tok.begin..tok.end].to_string()),
-                        TokenType::BlockquoteEnd => html.push("</blockquote>".to_string()),
-                        TokenType::ItalicBegin => html.push("<i>".to_string()),
-                        TokenType::ItalicEnd => html.push("</i>".to_string()),
-                        TokenType::BoldBegin => html.push("<b>".to_string()),
-                        TokenType::BoldEnd => html.push("</b>".to_string()),
-                        TokenType::StrikeBegin => html.push("<strike>".to_string()),
-                        TokenType::StrikeEnd => html.push("</strike>".to_string()),
-                        TokenType::UnderlineBegin => html.push("<u>".to_string()),
-                        TokenType::UnderlineEnd => html.push("</u>".to_string()),
-                        _ => break,
+            TokenType::Newline => {
+                iter.next();
+                break;
+            },
+            _ => break,
+        }
+    }
+
+    Ok(Node::Row { header, children: cells, span: Span::new(begin, end) })
+}
+
+fn parse_list(text: &str, tokens: &[Token], iter: &mut Tokens, options: &ParseOptions, t: &Token) -> Result<Node, Error> {
+    let item_end = |id: TokenType| matches!(id, TokenType::ListItemBegin|TokenType::ListItemEnd
+        |TokenType::UnorderedListEnd|TokenType::OrderedListEnd);
+    let mut items: Vec<Node> = Vec::new();
+    let mut end = t.end;
+    while let Some(n) = iter.peek() {
+        let n: &Token = n;
+        match n.id {
+            TokenType::ListItemBegin => {
+                iter.next();
+                let children = parse_blocks(text, tokens, iter, options, &item_end)?;
+                let mut item_end = children.last().map_or(n.end, |c| c.span().end);
+                if let Some(e) = iter.peek() {
+                    if e.id == TokenType::ListItemEnd {
+                        item_end = e.end;
+                        iter.next();
                     }
-                    iter.next();
                 }
-                match iter.peek() {
-                    Some(n) => {
-                        if n.id == TokenType::Newline {
-                            iter.next();
-                        }
-                    },
-                    None => (),
+                end = item_end;
+                items.push(Node::ListItem { children, span: Span::new(n.begin, item_end) });
+            },
+            TokenType::UnorderedListEnd|TokenType::OrderedListEnd => {
+                end = n.end;
+                iter.next();
+                break;
+            },
+            TokenType::ListItemEnd => {
+                iter.next();
+            },
+            _ => {
+                for node in parse_blocks(text, tokens, iter, options, &item_end)? {
+                    end = node.span().end;
+                    push_to_last_item(&mut items, node);
                 }
             },
-            TokenType::CodeBlockBegin => {
-                let lang_iter = match iter.peek() {
-                    Some(n) => match n.id {
-                            TokenType::CodeBlockLanguage => iter.next().unwrap(),
-                            _ => continue,
-                        },
-                    None => break,
+        }
+    }
+
+    let ordered = t.id == TokenType::OrderedListBegin;
+    // The begin token of an ordered list spans the number of its first item.
+    let start = if ordered { text[t.begin..t.end].parse().unwrap_or(1) } else { 1 };
+    let tight = !is_loose(text, &items);
+
+    Ok(Node::List { ordered, start, tight, children: items, span: Span::new(t.begin, end) })
+}
+
+/// Checks whether a blank line separates any of the items or any of the blocks directly inside an item.
+fn is_loose(text: &str, items: &[Node]) -> bool {
+    let separated = |nodes: &[Node]| nodes.windows(2).any(|w| has_blank_line(text, w[0].span().end, w[1].span().begin));
+
+    separated(items) || items.iter().any(|item| separated(item.children()))
+}
+
+/// Checks whether the text between the byte offsets `end` and `begin` contains a blank line.
+fn has_blank_line(text: &str, end: usize, begin: usize) -> bool {
+    // A node ending with its newline would otherwise hide the blank line following it.
+    let end = if end > 0 && text.as_bytes().get(end - 1) == Some(&b'\n') { end - 1 } else { end };
+    match text.get(end..begin) {
+        Some(gap) => {
+            let lines: Vec<&str> = gap.split('\n').collect();
+            lines.len() > 2 && lines[1..lines.len() - 1].iter().any(|l| l.trim().is_empty())
+        },
+        None => false,
+    }
+}
+
+/// Appends the node to the children of the last list item, creating one if there isn't any.
+fn push_to_last_item(items: &mut Vec<Node>, node: Node) {
+    if let Some(Node::ListItem { children, span }) = items.last_mut() {
+        span.end = span.end.max(node.span().end);
+        children.push(node);
+    } else {
+        let span = node.span();
+        items.push(Node::ListItem { children: vec![node], span });
+    }
+}
+
+/// Parses inline tokens until reaching a block token or a token for which `stop` returns true.
+fn parse_inline(text: &str, tokens: &[Token], iter: &mut Tokens, options: &ParseOptions, stop: &dyn Fn(TokenType) -> bool) -> Result<Vec<Node>, Error> {
+    let mut inline = Inline::new();
+    while let Some(t) = iter.peek() {
+        let t: &Token = t;
+        if stop(t.id) || starts_block(t.id) || ends_block(t.id) {
+            break;
+        }
+        iter.next();
+        inline.end = t.end;
+        let id = if options.is_enabled(t.id) { t.id } else { TokenType::Text };
+        match id {
+            TokenType::ItalicBegin => inline.open(Emphasis::Italic, t.begin),
+            TokenType::ItalicEnd => inline.close(Emphasis::Italic, text, t),
+            TokenType::BoldBegin => inline.open(Emphasis::Bold, t.begin),
+            TokenType::BoldEnd => inline.close(Emphasis::Bold, text, t),
+            TokenType::StrikeBegin => inline.open(Emphasis::Strike, t.begin),
+            TokenType::StrikeEnd => inline.close(Emphasis::Strike, text, t),
+            TokenType::UnderlineBegin => inline.open(Emphasis::Underline, t.begin),
+            TokenType::UnderlineEnd => inline.close(Emphasis::Underline, text, t),
+            TokenType::Checkbutton(checked) => inline.push(Node::Checkbox { checked, span: Span::new(t.begin, t.end) }),
+            TokenType::ImageAlt => {
+                let src = expect(tokens, iter, TokenType::ImageSrc)?;
+                let title = iter.next_if(|n| n.id == TokenType::ImageTitle);
+                let end = closing_paren(text, title.map_or(src.end, |n| n.end + 1));
+                inline.end = end;
+                inline.push(Node::Image {
+                    alt: text[t.begin..t.end].to_string(),
+                    src: link_url(text, src),
+                    title: title.map(|n| link_title(&text[n.begin..n.end])),
+                    // "- 2" To include the "![".
+                    span: Span::new(t.begin - 2, end),
+                });
+            },
+            TokenType::LinkHref => {
+                let title = iter.next_if(|n| n.id == TokenType::LinkTitle);
+                let link_text = expect(tokens, iter, TokenType::LinkText)?;
+                let content = if link_text.begin == link_text.end { t } else { link_text };
+                inline.push(Node::Link {
+                    href: link_url(text, t),
+                    title: title.map(|n| link_title(&text[n.begin..n.end])),
+                    children: vec![Node::Text { text: text[content.begin..content.end].to_string(), span: Span::new(content.begin, content.end) }],
+                    // "- 1" To include the "[".
+                    span: Span::new(link_text.begin - 1, closing_paren(text, title.map_or(t.end, |n| n.end + 1))),
+                });
+            },
+            TokenType::Autolink|TokenType::BareUrl => {
+                let url = &text[t.begin..t.end];
+                let href = if markdown::is_email(url) {
+                    format!("mailto:{}", url)
+                } else if url.starts_with("www.") {
+                    format!("http://{}", url)
+                } else {
+                    url.to_string()
                 };
-                let lang = text[lang_iter.begin..lang_iter.end - 1].to_string();
-                html.push(format!("<pre class=\"language {}\">\n", lang));
+                // "- 1" and "+ 1" To include the "<" and ">" of an autolink.
+                let span = if id == TokenType::Autolink { Span::new(t.begin - 1, t.end + 1) } else { Span::new(t.begin, t.end) };
+                inline.end = span.end;
+                inline.push(Node::Link {
+                    href,
+                    title: None,
+                    children: vec![Node::Text { text: url.to_string(), span: Span::new(t.begin, t.end) }],
+                    span,
+                });
+            },
+            TokenType::FootnoteReference => inline.push(Node::FootnoteReference {
+                // "+ 2" and "- 1" To leave out the "[^" and "]".
+                label: text[t.begin + 2..t.end - 1].to_string(),
+                number: 0,
+                index: 0,
+                span: Span::new(t.begin, t.end),
+            }),
+            TokenType::Code => inline.push(Node::Code { text: text[t.begin..t.end].to_string(), span: Span::new(t.begin - 1, t.end + 1) }),
+            // Only inline tokens can be escaped, a backslash ending its block or line is text.
+            TokenType::Escape => match iter.next_if(|n| !starts_block(n.id) && !ends_block(n.id)
+                && !matches!(n.id, TokenType::Newline|TokenType::HeadingUnderline)) {
+                // An escaped "[" doesn't start a link, the tokens of the link begin with its `LinkHref`.
+                Some(n) if n.id == TokenType::LinkHref => {
+                    let title = iter.next_if(|n| n.id == TokenType::LinkTitle);
+                    iter.next_if(|n| n.id == TokenType::LinkText);
+                    let end = closing_paren(text, title.map_or(n.end, |n| n.end + 1));
+                    inline.end = end;
+                    inline.push(Node::Text { text: text[t.end..end].to_string(), span: Span::new(t.end, end) });
+                },
+                Some(n) => {
+                    inline.end = n.end;
+                    inline.push(Node::Text { text: text[n.begin..n.end].to_string(), span: Span::new(n.begin, n.end) });
+                },
+                None => inline.push(Node::Text { text: text[t.begin..t.end].to_string(), span: Span::new(t.begin, t.end) }),
             },
-            TokenType::CodeBlockEnd => {
-                html.push("</pre>".to_string());
-                match iter.peek() {
-                    Some(n) => {
-                        if n.id == TokenType::Newline {
-                            iter.next();
-                        }
+            TokenType::Html => inline.push(Node::Html { html: text[t.begin..t.end].to_string(), span: Span::new(t.begin, t.end) }),
+            TokenType::Error => inline.push(Node::Error { text: text[t.begin..t.end].to_string(), span: Span::new(t.begin, t.end) }),
+            TokenType::Newline => inline.push(Node::SoftBreak { span: Span::new(t.begin, t.end) }),
+            TokenType::LineBreak => inline.push(Node::LineBreak { span: Span::new(t.begin, t.end) }),
+            TokenType::LinkText => {
+                let reference = match iter.next_if(|n| n.id == TokenType::LinkReference) {
+                    Some(reference) => reference,
+                    None => {
+                        // "- 1" and "+ 1" To include the brackets.
+                        let end = if text[t.end..].starts_with(']') { t.end + 1 } else { t.end };
+                        inline.push(Node::Text { text: text[t.begin - 1..end].to_string(), span: Span::new(t.begin - 1, end) });
+                        continue;
                     },
-                    None => (),
+                };
+                inline.end = reference.end;
+                // "- 1" To include the "[".
+                let span = Span::new(t.begin - 1, reference.end);
+                match iter.next_if(|n| n.id == TokenType::LinkHref) {
+                    Some(href) => {
+                        let title = iter.next_if(|n| n.id == TokenType::LinkTitle);
+                        inline.push(Node::Link {
+                            href: link_url(text, href),
+                            title: title.map(|n| link_title(&text[n.begin..n.end])),
+                            children: vec![Node::Text { text: text[t.begin..t.end].to_string(), span: Span::new(t.begin, t.end) }],
+                            span,
+                        });
+                    },
+                    _ => inline.push(Node::Text { text: text[span.begin..span.end].to_string(), span }),
                 }
             },
-            TokenType::CodeBlockText => html.push(text[t.begin..t.end].to_string()),
-            TokenType::CodeBlockDigit => html.push(format!("<span class=\"digit\">{}</span>", text[t.begin..t.end].to_string())),
-            TokenType::CodeBlockSymbol => html.push(format!("<span class=\"symbol\">{}</span>", text[t.begin..t.end].to_string())),
-            TokenType::CodeBlockChar => html.push(format!("<span class=\"char\">{}</span>", text[t.begin..t.end].to_string())),
-            TokenType::CodeBlockEscape => html.push(format!("<span class=\"escape\">{}</span>", text[t.begin..t.end].to_string())),
-            TokenType::CodeBlockFunction => html.push(format!("<span class=\"function\">{}</span>", text[t.begin..t.end].to_string())),
-            TokenType::CodeBlockKeyword1 => html.push(format!("<span class=\"keyword1\">{}</span>", text[t.begin..t.end].to_string())),
-            TokenType::CodeBlockKeyword2 => html.push(format!("<span class=\"keyword2\">{}</span>", text[t.begin..t.end].to_string())),
-            TokenType::CodeBlockKeyword3 => html.push(format!("<span class=\"keyword3\">{}</span>", text[t.begin..t.end].to_string())),
-            TokenType::CodeBlockString => html.push(format!("<span class=\"string\">{}</span>", text[t.begin..t.end].to_string())),
-            TokenType::CodeBlockSingleLineComment|
-            TokenType::CodeBlockMultiLineComment => html.push(format!("<span class=\"comment\">{}</span>", text[t.begin..t.end].to_string())),
-            TokenType::Escape => {
-                if let Some(v) = iter.next() {
-                    html.push(text[v.begin..v.end].to_string());
-                } else {
-                    break;
+            TokenType::ImageSrc|TokenType::PossibleTableStart
+            |TokenType::TableColumnLeft|TokenType::TableColumnRight|TokenType::TableColumnCenter => (),
+            _ => inline.push(Node::Text { text: text[t.begin..t.end].to_string(), span: Span::new(t.begin, t.end) }),
+        }
+    }
+
+    Ok(inline.finish())
+}
+
+/// Returns the byte offset after the `)` closing a link or image destination,
+/// `index` is somewhere after the url and title.
+fn closing_paren(text: &str, index: usize) -> usize {
+    text.get(index..).and_then(|rest| rest.find(')')).map_or(index, |i| index + i + 1)
+}
+
+/// Collects inline nodes while keeping track of the currently open emphasis.
+struct Inline {
+    children: Vec<Node>,
+    open: Vec<(Emphasis, usize, Vec<Node>)>,
+    end: usize,
+}
+
+impl Inline {
+    fn new() -> Inline {
+        Inline {
+            children: Vec::new(),
+            open: Vec::new(),
+            end: 0,
+        }
+    }
+
+    /// Adds the node to the innermost open emphasis, adjacent text is merged into a single node.
+    fn push(&mut self, node: Node) {
+        let children = match self.open.last_mut() {
+            Some(o) => &mut o.2,
+            None => &mut self.children,
+        };
+        if let Node::Text { text, span } = &node {
+            if let Some(Node::Text { text: last_text, span: last_span }) = children.last_mut() {
+                if last_span.end == span.begin {
+                    last_text.push_str(text);
+                    last_span.end = span.end;
+                    return;
                 }
-            },
-            TokenType::TableBegin => {
-                html.push("<table>\n<tr>\n".to_string());
-                let mut columns: Vec<Column> = Vec::with_capacity(15);
-                loop {
-                    match iter.peek() {
-                        Some(n) => {
-                            match n.id {
-                                TokenType::TableColumnLeft => {
-                                    columns.push(Column(columns.len(), "left".to_string()));
-                                    iter.next();
-                                },
-                                TokenType::TableColumnRight => {
-                                    columns.push(Column(columns.len(), "right".to_string()));
-                                    iter.next();
-                                },
-                                TokenType::TableColumnCenter => {
-                                    columns.push(Column(columns.len(), "center".to_string()));
-                                    iter.next();
-                                },
-                                _ => break,
-                            }
-                        },
-                        None => (),
-                    }
+            }
+        }
+        children.push(node);
+    }
+
+    fn open(&mut self, kind: Emphasis, begin: usize) {
+        self.open.push((kind, begin, Vec::new()));
+    }
+
+    /// Closes the innermost emphasis of `kind` at the end of the token `t`.
+    /// Emphasis opened after it gets closed as well and then reopened,
+    /// so overlapping emphasis like `**a *b** c*` still produces a tree.
+    /// Without an open emphasis of `kind`, like when it was opened in another block, the marker is text.
+    fn close(&mut self, kind: Emphasis, text: &str, t: &Token) {
+        match self.open.iter().rposition(|o| o.0 == kind) {
+            Some(i) => {
+                let reopen: Vec<Emphasis> = self.open[i + 1..].iter().map(|o| o.0).collect();
+                self.close_to(i, t.end);
+                for k in reopen {
+                    self.open(k, t.end);
                 }
-                let mut heading_count = columns.len();
-                let mut heading = true;
-                let mut cell_count = columns.len();
-                loop {
-                    match iter.next() {
-                        Some(n) => {
-                            match n.id {
-                                TokenType::Pipe => {
-                                    if heading {
-                                        if heading_count > 0 {
-                                            if heading_count == columns.len() {
-                                                html.push(format!("<th align=\"{}\">\n", columns[heading_count - 1].1));
-                                            } else {
-                                                html.push(format!("\n</th>\n<th align=\"{}\">\n", columns[heading_count - 1].1));
-                                            }
-                                            heading_count -= 1;
-                                        } else {
-                                            html.push("\n</th>\n".to_string());
-                                            html.push("</tr>\n".to_string());
-                                            heading = false;
-                                            match iter.peek() {
-                                                Some(n) => {
-                                                    if n.id == TokenType::Newline {
-                                                        iter.next();
-                                                    }
-                                                },
-                                                None => (),
-                                            }
-                                        }
-                                    } else {
-                                        if cell_count > 0 {
-                                            if cell_count == columns.len() {
-                                                html.push(format!("<tr>\n<td align=\"{}\">\n", columns[cell_count - 1].1));
-                                            } else {
-                                                html.push(format!("\n</td>\n<td align=\"{}\">\n", columns[cell_count - 1].1));
-                                            }
-                                            cell_count -= 1;
-                                        } else {
-                                            html.push("\n</td>\n".to_string());
-                                        }
-                                    }
-                                },
-                                TokenType::Newline => {
-                                    html.push("</tr>\n".to_string());
-                                    cell_count = columns.len();
-                                },
-                                TokenType::Checkbutton(bool) => {
-                                    if n.id == TokenType::Checkbutton(true) {
-                                        html.push(format!("<input type=\"checkbox\" checked>"));
-                                    } else {
-                                        html.push(format!("<input type=\"checkbox\">"));
-                                    }
-                                },
-                                TokenType::ImageAlt => {
-                                    html.push(format!("<img alt=\"{}\"", text[n.begin..n.end].to_string()));
-                                    let n = iter.next().unwrap();
-                                    html.push(format!(" src=\"{}\">", text[n.begin..n.end].to_string()));
-                                },
-                                TokenType::LinkHref => {
-                                    html.push(format!("<a href=\"{}\">", text[n.begin..n.end].to_string()));
-                                    let tok = iter.next().unwrap();
-                                    if text[tok.begin..tok.end].len() == 0 {
-                                        html.push(format!("{}</a>", text[n.begin..n.end].to_string()));
-                                    } else {
-                                        html.push(format!("{}</a>", text[tok.begin..tok.end].to_string()));
-                                    }
-                                },
-                                TokenType::TableEnd => {
-                                    html.push("</table>\n".to_string());
-                                    break;
-                                },
-                                TokenType::Code => html.push(format!("<code>{}</code>", text[n.begin..n.end].to_string())),
-                                TokenType::ItalicBegin => html.push("<i>".to_string()),
-                                TokenType::ItalicEnd => html.push("</i>".to_string()),
-                                TokenType::BoldBegin => html.push("<b>".to_string()),
-                                TokenType::BoldEnd => html.push("</b>".to_string()),
-                                TokenType::StrikeBegin => html.push("<strike>".to_string()),
-                                TokenType::StrikeEnd => html.push("</strike>".to_string()),
-                                TokenType::UnderlineBegin => html.push("<u>".to_string()),
-                                TokenType::UnderlineEnd => html.push("</u>".to_string()),
-                                TokenType::Error => html.push(format!("<span class=\"error\">ERROR: {}</span>\n", text[n.begin..n.end].to_string())),
-                                _ => html.push(text[n.begin..n.end].to_string()),
-                            }
-                        },
-                        None => (),
-                    }
+            },
+            None => self.push(Node::Text { text: text[t.begin..t.end].to_string(), span: Span::new(t.begin, t.end) }),
+        }
+    }
+
+    /// Closes the open emphasis down to `depth`, emphasis without any content is dropped.
+    fn close_to(&mut self, depth: usize, end: usize) {
+        while self.open.len() > depth {
+            if let Some((kind, begin, children)) = self.open.pop() {
+                if !children.is_empty() {
+                    self.push(Node::Emphasis { kind, children, span: Span::new(begin, end) });
                 }
+            }
+        }
+    }
+
+    /// Closes all emphasis that is still open and returns the collected nodes.
+    fn finish(mut self) -> Vec<Node> {
+        let end = self.end;
+        self.close_to(0, end);
+        self.children
+    }
+}
+
+fn highlight(id: TokenType) -> Option<Highlight> {
+    match id {
+        TokenType::CodeBlockText => Some(Highlight::Text),
+        TokenType::CodeBlockString => Some(Highlight::String),
+        TokenType::CodeBlockChar => Some(Highlight::Char),
+        TokenType::CodeBlockDigit => Some(Highlight::Digit),
+        TokenType::CodeBlockKeyword1 => Some(Highlight::Keyword1),
+        TokenType::CodeBlockKeyword2 => Some(Highlight::Keyword2),
+        TokenType::CodeBlockKeyword3 => Some(Highlight::Keyword3),
+        TokenType::CodeBlockSymbol => Some(Highlight::Symbol),
+        TokenType::CodeBlockFunction => Some(Highlight::Function),
+        TokenType::CodeBlockSingleLineComment|TokenType::CodeBlockMultiLineComment => Some(Highlight::Comment),
+        TokenType::CodeBlockEscape => Some(Highlight::Escape),
+        _ => None,
+    }
+}
+
+fn is_blank(nodes: &[Node]) -> bool {
+    nodes.iter().all(|n| match n {
+        Node::Text { text, .. } => text.trim().is_empty(),
+        _ => false,
+    })
+}
+
+fn skip_newline(iter: &mut Tokens) {
+    skip(iter, TokenType::Newline);
+}
+
+/// Consumes the next token if it is of type `id`.
+fn skip(iter: &mut Tokens, id: TokenType) {
+    if let Some(n) = iter.peek() {
+        if n.id == id {
+            iter.next();
+        }
+    }
+}
+
+/// Returns the next token if it is of the `expected` type.
+fn expect<'a>(tokens: &[Token], iter: &mut Tokens<'a>, expected: TokenType) -> Result<&'a Token, Error> {
+    let index = tokens.len() - iter.len();
+    match iter.next() {
+        Some(t) if t.id == expected => Ok(t),
+        _ => Err(Error::MalformedTokens { index, expected }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic::Diagnostic;
+
+    #[test]
+    fn heading() -> Result<(), Error> {
+        let d = crate::parse("## Title\nnext")?;
+        assert_eq!(d.children[0], Node::Heading {
+            level: 2,
+            id: "title".to_string(),
+            children: vec![Node::Text { text: "Title".to_string(), span: Span::new(3, 8) }],
+            span: Span::new(0, 8),
+        });
+        assert_eq!(d.children[1], Node::Paragraph {
+            children: vec![Node::Text { text: "next".to_string(), span: Span::new(9, 13) }],
+            span: Span::new(9, 13),
+        });
+
+        Ok(())
+    }
+
+    #[test]
+    fn heading_forms() -> Result<(), Error> {
+        let d = crate::parse("Title\n===\n\nSub\n---\n\n# Closed ##\n## C#\n### ###\n")?;
+        let headings: Vec<(usize, String)> = d.children.iter().map(|n| match n {
+            Node::Heading { level, children, .. } => (*level, children.iter().map(|c| match c {
+                Node::Text { text, .. } => text.as_str(),
+                _ => "",
+            }).collect()),
+            n => panic!("Unexpected node {:#?}", n),
+        }).collect();
+        assert_eq!(headings, vec![
+            (1, "Title".to_string()),
+            (2, "Sub".to_string()),
+            (1, "Closed".to_string()),
+            (2, "C#".to_string()),
+            (3, "".to_string()),
+        ]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn heading_inline() -> Result<(), Error> {
+        let d = crate::parse("## The `lex` *fn* \\# ##\nnext\n")?;
+        match d.children[0].children() {
+            [Node::Text { text: a, .. }, Node::Code { text: code, .. }, Node::Text { .. }, Node::Emphasis { kind: Emphasis::Italic, .. }, Node::Text { .. }, Node::Text { text: b, span }] => {
+                assert_eq!(a, "The ");
+                assert_eq!(code, "lex");
+                assert_eq!(b, "#");
+                assert_eq!(span.end, 20);
+            },
+            n => panic!("Unexpected nodes {:#?}", n),
+        }
+        assert!(matches!(d.children[1], Node::Paragraph { .. }));
+
+        let d = crate::parse("# [a](b) ![c](d) `#` #\n")?;
+        assert!(matches!(d.children[0].children(), [Node::Link { .. }, Node::Text { .. }, Node::Image { .. }, Node::Text { .. }, Node::Code { .. }]), "{:#?}", d.children[0]);
+        // Emphasis opened in the heading doesn't continue on the next line.
+        let d = crate::parse("# *a\nb*\n")?;
+        assert!(matches!(d.children[0].children(), [Node::Emphasis { kind: Emphasis::Italic, .. }]), "{:#?}", d.children[0]);
+        assert_eq!(d.children[1].plain_text(), "b*");
+
+        Ok(())
+    }
+
+    #[test]
+    fn heading_ids() -> Result<(), Error> {
+        let d = crate::parse("# Install
+## Install
+# Setup {#install-1}
+Usage {#usage}
+---
+
+* # *Install* it
+## {#usage} {#x y}
+")?;
+        let ids: Vec<&str> = d.children.iter().chain(d.children[4].children()[0].children()).filter_map(|n| match n {
+            Node::Heading { id, .. } => Some(id.as_str()),
+            _ => None,
+        }).collect();
+        assert_eq!(ids, vec!["install", "install-2", "install-1", "usage", "usage-x-y", "install-it"]);
+        assert_eq!(d.children[2].plain_text(), "Setup");
+        assert_eq!(d.children[3].plain_text(), "Usage");
+        assert_eq!(d.children[5].plain_text(), "{#usage} {#x y}");
+        // The ids of footnotes are taken as well.
+        let d = crate::parse("# fn 1\n## fnref 1\n\na[^1]\n\n[^1]: x\n")?;
+        assert!(matches!(&d.children[0], Node::Heading { id, .. } if id == "fn-1-1"));
+        assert!(matches!(&d.children[1], Node::Heading { id, .. } if id == "fnref-1-1"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn references() -> Result<(), Error> {
+        let d = crate::parse("* [a][Two  Words] [b][missing] [c] [d][]\n\n[two words]: /one\n[TWO WORDS]: /two\n[c]: <my url> 'T'\n")?;
+        let links: Vec<(String, String)> = d.children[0].children()[0].children()[0].children().iter().filter_map(|n| match n {
+            Node::Link { href, children, .. } => Some((href.clone(), children[0].plain_text())),
+            _ => None,
+        }).collect();
+        assert_eq!(links, vec![("/one".to_string(), "a".to_string()), ("my%20url".to_string(), "c".to_string())]);
+        assert_eq!(d.children[0].plain_text(), "a [b][missing] c [d][]");
+        assert_eq!(d.children.len(), 1);
+        assert_eq!(d.diagnostics, vec![
+            Diagnostic::new("undefined link reference 'missing'".to_string(), Span::new(18, 30)),
+            Diagnostic::new("undefined link reference 'd'".to_string(), Span::new(35, 40)),
+        ]);
+
+        // Brackets that aren't followed by a reference stay text.
+        assert_eq!(crate::parse("\\[a](b)[c")?.children[0].plain_text(), "[a](b)[c");
+        assert_eq!(crate::parse("# [x][[y]\n")?.children[0].plain_text(), "[x][[y]");
+
+        Ok(())
+    }
+
+    #[test]
+    fn code_fences() -> Result<(), Error> {
+        let code = |node: &Node| -> String { node.children().iter().map(|c| c.plain_text()).collect() };
+        let d = crate::parse("~~~~ rust {linenos=true title=\"a b.rs\" wrap}\n~~~\n```\n~~~~\n  ```\n  a\n   b\n  ```\n````\n```")?;
+        match d.children.as_slice() {
+            [first @ Node::CodeBlock { language, attributes, span, .. }, second @ Node::CodeBlock { .. }, third @ Node::CodeBlock { span: eof, .. }] => {
+                assert_eq!(language, "rust");
+                assert_eq!(attributes, &vec![
+                    ("linenos".to_string(), "true".to_string()),
+                    ("title".to_string(), "a b.rs".to_string()),
+                    ("wrap".to_string(), String::new()),
+                ]);
+                assert_eq!(code(first), "~~~\n```\n");
+                assert_eq!(*span, Span::new(0, 57));
+                assert_eq!(code(second), "a\n b\n");
+                assert_eq!(code(third), "```");
+                assert_eq!(eof.end, 87);
+            },
+            n => panic!("Unexpected nodes {:#?}", n),
+        }
+        let d = crate::parse("```c {linenos linenostart=3} {2,5-4}\nx\n```\n```c linenos=false hl_lines=\"1 3-4\" {x}\nx\n```\n")?;
+        match d.children.as_slice() {
+            [Node::CodeBlock { line_numbers: true, first_line: 3, highlighted_lines, .. }, Node::CodeBlock { line_numbers: false, first_line: 1, highlighted_lines: other, .. }] => {
+                assert_eq!(highlighted_lines, &vec![(2, 2), (4, 5)]);
+                assert_eq!(other, &vec![(1, 1), (3, 4)]);
+            },
+            n => panic!("Unexpected nodes {:#?}", n),
+        }
+        let d = crate::parse("```c {linenos=true,linenostart=5}\nx\n```\n```c {linenos, hl_lines=\"2,3\"}\nx\n```\n```c {linenos,2}\nx\n```\n")?;
+        match d.children.as_slice() {
+            [Node::CodeBlock { attributes, line_numbers: true, first_line: 5, .. }, Node::CodeBlock { attributes: quoted, line_numbers: true, highlighted_lines, .. }, Node::CodeBlock { line_numbers: true, highlighted_lines: bare, .. }] => {
+                assert_eq!(attributes, &vec![
+                    ("linenos".to_string(), "true".to_string()),
+                    ("linenostart".to_string(), "5".to_string()),
+                ]);
+                assert_eq!(quoted, &vec![
+                    ("linenos".to_string(), String::new()),
+                    ("hl_lines".to_string(), "2,3".to_string()),
+                ]);
+                assert_eq!(highlighted_lines, &vec![(2, 2), (3, 3)]);
+                assert_eq!(bare, &vec![(2, 2)]);
+            },
+            n => panic!("Unexpected nodes {:#?}", n),
+        }
+        // The indentation is removed from lines inside of strings and comments as well.
+        let d = crate::parse("  ```c\n  \"x\n  y\"\n  /* a\n     b */\n  ```\n")?;
+        assert_eq!(code(&d.children[0]), "\"x\ny\"\n/* a\n   b */\n");
+        let d = crate::parse("```\n```x\n``` not a fence\n")?;
+        assert_eq!(d.children.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn blockquotes() -> Result<(), Error> {
+        let d = crate::parse("> a `b`\nlazy\n>> c\n\n> 1. d\n>\n> ```\n> x\n> ```\n")?;
+        assert_eq!(d.children.len(), 2);
+        match d.children[0].children() {
+            [Node::Paragraph { children, .. }, Node::Blockquote { children: nested, span }] => {
+                assert_eq!(children.len(), 4);
+                assert!(matches!(nested.as_slice(), [Node::Paragraph { .. }]));
+                assert_eq!(*span, Span::new(14, 17));
+            },
+            n => panic!("Unexpected nodes {:#?}", n),
+        }
+        assert_eq!(d.children[0].span(), Span::new(0, 17));
+        assert!(matches!(d.children[1].children(), [Node::List { ordered: true, .. }, Node::CodeBlock { .. }]), "{:#?}", d.children[1]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn blockquote_blocks() -> Result<(), Error> {
+        let d = crate::parse("> a\nlazy\n> b\n\n> # A\n>> ## B\n>> text\n>\n> c\n")?;
+        assert_eq!(d.children.len(), 2);
+        assert!(matches!(d.children[0].children(), [Node::Paragraph { .. }]));
+        assert_eq!(d.children[0].plain_text(), "a lazy b");
+        match d.children[1].children() {
+            [Node::Heading { level: 1, .. }, Node::Blockquote { children, .. }, Node::Paragraph { .. }] => {
+                assert!(matches!(children.as_slice(), [Node::Heading { level: 2, .. }, Node::Paragraph { .. }]), "{:#?}", children);
+            },
+            n => panic!("Unexpected nodes {:#?}", n),
+        }
+
+        let d = crate::parse("> Title\n> ---\n> body\n\n> ```rust\n> let x = 1;\n> ```\n")?;
+        assert!(matches!(d.children[0].children(), [Node::Heading { level: 2, .. }, Node::Paragraph { .. }]), "{:#?}", d.children[0]);
+        match d.children[1].children() {
+            [code @ Node::CodeBlock { language, .. }] => {
+                assert_eq!(language, "rust");
+                assert_eq!(code.children().iter().map(|c| c.plain_text()).collect::<String>(), "let x = 1;\n");
             },
-            TokenType::UnorderedListBegin => html.push("\n<ul>\n".to_string()),
-            TokenType::UnorderedListEnd => html.push("\n</ul>\n".to_string()),
-            TokenType::OrderedListBegin => html.push("\n<ol>\n".to_string()),
-            TokenType::OrderedListEnd => html.push("\n</ol>\n".to_string()),
-            TokenType::ListItemBegin => html.push("<li>".to_string()),
-            TokenType::ListItemEnd => html.push("</li>".to_string()),
-            TokenType::HorizontalRule => html.push("<hr>\n".to_string()),
-            TokenType::Code => html.push(format!("<code>{}</code>", text[t.begin..t.end].to_string())),
-            TokenType::IndentBlock => html.push(format!("<pre>{}</pre>", text[t.begin + 4..t.end].replace("\n    ", "\n").replace("<", "&#60").replace(">", "&#62"))),
-            TokenType::ItalicBegin => html.push("<i>".to_string()),
-            TokenType::ItalicEnd => html.push("</i>".to_string()),
-            TokenType::BoldBegin => html.push("<b>".to_string()),
-            TokenType::BoldEnd => html.push("</b>".to_string()),
-            TokenType::StrikeBegin => html.push("<strike>".to_string()),
-            TokenType::StrikeEnd => html.push("</strike>".to_string()),
-            TokenType::UnderlineBegin => html.push("<u>".to_string()),
-            TokenType::UnderlineEnd => html.push("</u>".to_string()),
-            TokenType::Pipe => html.push(text[t.begin..t.end].to_string()),
-            TokenType::Html => {
-                html.push(text[t.begin..=t.end].to_string());
-                match iter.peek() {
-                    Some(n) => {
-                        if n.id == TokenType::Newline {
-                            iter.next();
-                        }
+            n => panic!("Unexpected nodes {:#?}", n),
+        }
+
+        // An underline that isn't inside of the quote doesn't turn its text into a heading.
+        for text in ["> a\n=", ">a\n=\n"] {
+            let d = crate::parse(text)?;
+            assert!(matches!(d.children.as_slice(), [Node::Blockquote { .. }, Node::Paragraph { .. }]), "{:#?}", d.children);
+        }
+        let d = crate::parse("> # a>\n")?;
+        assert!(matches!(d.children[0].children(), [Node::Heading { level: 1, .. }]), "{:#?}", d.children[0]);
+        assert_eq!(d.children[0].plain_text(), "a>");
+
+        Ok(())
+    }
+
+    #[test]
+    fn footnotes() -> Result<(), Error> {
+        let d = crate::parse("* a[^n] b[^missing] c[^N]\n\n[^n]: Note\n\n    more\n[^u]: unused\n")?;
+        assert_eq!(d.children.len(), 2);
+        match d.children[0].children()[0].children()[0].children() {
+            [Node::Text { .. }, Node::FootnoteReference { number: 1, index: 1, span, .. }, Node::Text { text, .. }, Node::FootnoteReference { number: 1, index: 2, .. }] => {
+                assert_eq!(*span, Span::new(3, 7));
+                assert_eq!(text, " b[^missing] c");
+            },
+            n => panic!("Unexpected nodes {:#?}", n),
+        }
+        match &d.children[1] {
+            Node::Footnotes { children, .. } => match children.as_slice() {
+                [Node::FootnoteDefinition { label, number: 1, references: 2, children, span }] => {
+                    assert_eq!(label, "n");
+                    assert_eq!(children.len(), 2);
+                    assert_eq!(*span, Span::new(27, 47));
+                },
+                n => panic!("Unexpected nodes {:#?}", n),
+            },
+            n => panic!("Unexpected node {:#?}", n),
+        }
+        assert_eq!(d.diagnostics, vec![
+            Diagnostic::new("undefined footnote 'missing'".to_string(), Span::new(9, 19)),
+            Diagnostic::new("unused footnote 'u'".to_string(), Span::new(48, 60)),
+        ]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn link_titles() -> Result<(), Error> {
+        let d = crate::parse("[a](/u \"T 1\") ![b](<my img.png> (T 2)) [c](/f(x)) [d](/u 'x' y)\n")?;
+        match d.children[0].children() {
+            [Node::Link { href, title: Some(title), span, .. }, Node::Text { .. }, Node::Image { src, title: Some(image_title), span: image_span, .. }, Node::Text { .. }, Node::Link { href: c, title: None, .. }, Node::Text { .. }, Node::Link { href: d, title: None, .. }] => {
+                assert_eq!((href.as_str(), title.as_str(), *span), ("/u", "T 1", Span::new(0, 13)));
+                assert_eq!((src.as_str(), image_title.as_str(), *image_span), ("my%20img.png", "T 2", Span::new(14, 38)));
+                assert_eq!(c, "/f(x)");
+                assert_eq!(d, "/u 'x' y");
+            },
+            n => panic!("Unexpected nodes {:#?}", n),
+        }
+
+        let link = |text: &str| -> Result<(String, Option<String>), Error> {
+            match crate::parse(text)?.children[0].children() {
+                [Node::Link { href, title, .. }]|[Node::Image { src: href, title, .. }] => Ok((href.clone(), title.clone())),
+                n => panic!("Unexpected nodes {:#?}", n),
+            }
+        };
+        assert_eq!(link("[a]( /u  \"t\" )\n")?, ("/u".to_string(), Some("t".to_string())));
+        assert_eq!(link("[a](/u \"\")\n")?, ("/u".to_string(), Some(String::new())));
+        assert_eq!(link("![i](/p.png \"a (b) c\")\n")?, ("/p.png".to_string(), Some("a (b) c".to_string())));
+        // An unclosed title or one without whitespace before it is part of the url.
+        assert_eq!(link("[a](/u \"x)\n")?, ("/u \"x".to_string(), None));
+        assert_eq!(link("[a](/u\"t\")\n")?, ("/u\"t\"".to_string(), None));
+        // Escaped quotes don't close the title, spaces in a "<>" url are percent encoded.
+        assert_eq!(link("[a](<b c> \"a \\\"b\\\"\")\n")?, ("b%20c".to_string(), Some("a \"b\"".to_string())));
+        assert_eq!(link("[a](/u 'it\\'s \\\\')\n")?, ("/u".to_string(), Some("it's \\".to_string())));
+        assert_eq!(link("[a][r]\n\n[r]: <x y> (1 \\) 2)\n")?, ("x%20y".to_string(), Some("1 ) 2".to_string())));
+
+        Ok(())
+    }
+
+    #[test]
+    fn autolinks() -> Result<(), Error> {
+        let d = crate::parse("<ftp://a.b/c> <irc:x> <HTTPS://A.B> <a@b> <me@b.co.> <https://a.b/x y>\n")?;
+        let links: Vec<&str> = d.children[0].children().iter().filter_map(|n| match n {
+            Node::Link { href, .. } => Some(href.as_str()),
+            _ => None,
+        }).collect();
+        assert_eq!(links, vec!["ftp://a.b/c", "irc:x", "HTTPS://A.B", "mailto:a@b"]);
+        assert!(d.children[0].plain_text().ends_with(" <me@b.co.> <https://a.b/x y>"), "{:#?}", d.children[0]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn emphasis() -> Result<(), Error> {
+        let d = crate::parse("**a *b** c* d")?;
+        match d.children[0].children() {
+            [Node::Emphasis { kind: Emphasis::Bold, children: bold, .. }, Node::Emphasis { kind: Emphasis::Italic, children: italic, .. }, Node::Text { .. }] => {
+                assert_eq!(bold.len(), 2);
+                assert!(matches!(bold[1], Node::Emphasis { kind: Emphasis::Italic, .. }));
+                assert_eq!(italic, &vec![Node::Text { text: " c".to_string(), span: Span::new(8, 10) }]);
+            },
+            n => panic!("Unexpected nodes {:#?}", n),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn trailing_backslash() -> Result<(), Error> {
+        assert_eq!(crate::to_html("abc\\")?, "<p>abc\\</p>\n");
+        assert_eq!(crate::to_html("- item\\\n")?, "\n<ul>\n<li>item\\</li>\n</ul>\n");
+        assert_eq!(crate::to_html("> q\\\n")?, "<blockquote>\n<p>q\\</p>\n</blockquote>\n");
+        let d = crate::parse("abc\\")?;
+        assert_eq!(d.children[0].children(), &[Node::Text { text: "abc\\".to_string(), span: Span::new(0, 4) }]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn list() -> Result<(), Error> {
+        let d = crate::parse("a\n\n* one\n    1. two\n* three\n\n")?;
+        let list = d.children.iter().find(|n| matches!(n, Node::List { .. })).unwrap();
+        assert_eq!(list.children().len(), 2);
+        match &list.children()[0].children()[1] {
+            Node::List { ordered: true, children, .. } => assert_eq!(children.len(), 1),
+            n => panic!("Unexpected node {:#?}", n),
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn nested_blocks() -> Result<(), Error> {
+        let text = "1. Install\n\n   ```sh\n   make install\n   ```\n\n   > Needs root\n\n   Then restart.\n2. Done\n";
+        let d = crate::parse(text)?;
+        assert_eq!(d.children.len(), 1);
+        match &d.children[0] {
+            Node::List { tight: false, children, .. } => {
+                assert_eq!(children.len(), 2);
+                match children[0].children() {
+                    [Node::Paragraph { .. }, Node::CodeBlock { language, .. }, Node::Blockquote { .. }, Node::Paragraph { span, .. }] => {
+                        assert_eq!(language, "sh");
+                        assert_eq!(&text[span.begin..span.end], "Then restart.");
                     },
-                    None => (),
+                    n => panic!("Unexpected nodes {:#?}", n),
                 }
             },
-            TokenType::Error => html.push(format!("<span class=\"error\">ERROR: {}</span>\n", text[t.begin..t.end].to_string())),
-            TokenType::Newline => html.push("<br>\n".to_string()),
-            TokenType::Text => html.push(text[t.begin..t.end].to_string()),
-            TokenType::Space => html.push(text[t.begin..t.end].to_string()),
-            TokenType::Tab => html.push(text[t.begin..t.end].to_string()),
-            _ => (),
+            n => panic!("Unexpected node {:#?}", n),
         }
+
+        assert!(matches!(crate::parse("- a\n- b\n")?.children[0], Node::List { tight: true, .. }));
+        assert!(matches!(crate::parse("- a\n\n- b\n")?.children[0], Node::List { tight: false, .. }));
+        assert!(matches!(crate::parse("- a\n  - b\n\n  - c\n")?.children[0], Node::List { tight: true, .. }));
+        assert!(matches!(crate::parse("- a\n\n  b\n- c\n")?.children[0], Node::List { tight: false, .. }));
+        assert!(matches!(crate::parse("- a\n\n  > q\n- c\n")?.children[0], Node::List { tight: false, .. }));
+        let d = crate::parse("- a\n- b\n\nafter\n")?;
+        assert!(matches!(d.children.as_slice(), [Node::List { tight: true, .. }, Node::Paragraph { .. }]), "{:#?}", d.children);
+        // Only the nested list is loose, the blank line is inside of its item.
+        let d = crate::parse("1. a\n   - b\n\n     c\n2. d\n")?;
+        match &d.children[0] {
+            Node::List { tight: true, children, .. } => assert!(matches!(children[0].children()[1], Node::List { tight: false, .. })),
+            n => panic!("Unexpected node {:#?}", n),
+        }
+        let d = crate::parse("- a\nlazy\n\nafter\n")?;
+        assert_eq!(d.children[0].children()[0].children()[0].children().len(), 3);
+        assert!(matches!(d.children[1], Node::Paragraph { .. }));
+
+        Ok(())
     }
 
-    html
-}
+    #[test]
+    fn deep_nesting() -> Result<(), Error> {
+        let d = crate::parse(&format!("{}x\n", "- ".repeat(10000)))?;
+        let mut depth = 0;
+        let mut node = &d.children[0];
+        while let Node::List { children, .. } = node {
+            depth += 1;
+            match children[0].children().first() {
+                Some(n) => node = n,
+                None => break,
+            }
+        }
+        assert_eq!(depth, markdown::MAX_NESTING);
+        assert!(crate::parse(&format!("{}x\n", "> - ".repeat(5000))).is_ok());
 
-pub fn generate_html(output_file: String, html: Vec<String>, css_file: &str) {
-    let mut file = fs::File::create(output_file).unwrap();
-    if css_file != "" {
-        file.write("<style>\n".as_bytes()).unwrap();
-        file.write(fs::read_to_string(css_file).unwrap().as_bytes()).unwrap();
-        file.write("</style>\n".as_bytes()).unwrap();
+        Ok(())
     }
-    for tag in html.iter() {
-        file.write(tag.as_bytes()).unwrap();
+
+    #[test]
+    fn ordered_list() -> Result<(), Error> {
+        let d = crate::parse("a\n\n5. five\n6. six\n    1) one\n\nb\n\n10) ten\n\nThe year was\n1984. A good year\n")?;
+        match &d.children[1] {
+            Node::List { ordered: true, start: 5, tight: true, children, span } => {
+                assert_eq!(span.begin, 3);
+                assert_eq!(children.len(), 2);
+                assert!(matches!(children[1].children()[1], Node::List { ordered: true, start: 1, .. }));
+            },
+            n => panic!("Unexpected node {:#?}", n),
+        }
+        assert!(matches!(d.children[3], Node::List { ordered: true, start: 10, .. }));
+        assert!(matches!(d.children[4], Node::Paragraph { .. }));
+        assert_eq!(d.children.len(), 5);
+
+        Ok(())
     }
-}
 
-//TODO Add parser tests
+    #[test]
+    fn table() -> Result<(), Error> {
+        let d = crate::parse("a\n\n| a | b |\n| --- |:---:|\n| *c* | d |\n\n")?;
+        let table = d.children.iter().find(|n| matches!(n, Node::Table { .. })).unwrap();
+        assert_eq!(table.children().len(), 2);
+        match &table.children()[1] {
+            Node::Row { header: false, children, .. } => {
+                assert!(matches!(children[0], Node::Cell { alignment: Alignment::Left, .. }));
+                assert!(matches!(children[1], Node::Cell { alignment: Alignment::Center, .. }));
+                assert!(matches!(children[0].children()[1], Node::Emphasis { kind: Emphasis::Italic, .. }));
+            },
+            n => panic!("Unexpected node {:#?}", n),
+        }
+
+        Ok(())
+    }
+}
@@ -1,4 +1,10 @@
-#[derive(Debug, PartialEq)]
+use crate::token::Token;
+
+/// Struct describing a location in the text.
+///
+/// `index` is a byte offset, `line` and `line_offset` (the column) start at 0.
+/// The column is counted in chars, not bytes.
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Position {
     pub index: usize,
     pub line: usize,
@@ -28,9 +34,14 @@ impl Position {
         self.line_offset = position.line_offset;
     }
 
-    pub fn increment(&mut self) {
-        self.index += 1;
-        self.line_offset += 1;
+    /// Moves the position past the char `c` found at byte offset `c.0`.
+    pub fn advance(&mut self, c: (usize, char)) {
+        self.index = c.0 + c.1.len_utf8();
+        if c.1 == '\n' {
+            self.newline();
+        } else {
+            self.line_offset += 1;
+        }
     }
 
     pub fn newline(&mut self) {
@@ -50,3 +61,87 @@ impl Position {
         self.line_offset = line_offset;
     }
 }
+
+/// Lookup table for converting byte offsets into line and column positions.
+///
+/// Building the table is a single pass over the text,
+/// afterwards every lookup is a binary search over the line starts.
+#[derive(Debug)]
+pub struct LineIndex<'a> {
+    text: &'a str,
+    line_starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    pub fn new(text: &'a str) -> LineIndex<'a> {
+        let mut line_starts: Vec<usize> = vec![0];
+        for (i, b) in text.bytes().enumerate() {
+            if b == b'\n' {
+                line_starts.push(i + 1);
+            }
+        }
+
+        LineIndex {
+            text,
+            line_starts,
+        }
+    }
+
+    /// Returns the `Position` of the byte offset `index`.
+    /// Offsets past the end of the text are clamped to its length.
+    pub fn position(&self, index: usize) -> Position {
+        let mut index = index.min(self.text.len());
+        while !self.text.is_char_boundary(index) {
+            index -= 1;
+        }
+        let line = match self.line_starts.binary_search(&index) {
+            Ok(line) => line,
+            Err(line) => line - 1,
+        };
+        let line_begin = self.line_starts[line];
+
+        Position::new_at(index, line, self.text[line_begin..index].chars().count())
+    }
+
+    /// Returns the `Position` of the beginning and the end of the token.
+    pub fn span(&self, token: &Token) -> (Position, Position) {
+        (self.position(token.begin), self.position(token.end))
+    }
+
+    /// Returns the number of lines in the text.
+    pub fn line_count(&self) -> usize {
+        self.line_starts.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wrapper::CharsWithPosition;
+
+    #[test]
+    fn line_index() {
+        let text = "# Zażółć\n\nab *ü*\n";
+        let lines = LineIndex::new(text);
+        assert_eq!(lines.line_count(), 4);
+        assert_eq!(lines.position(0), Position::new_at(0, 0, 0));
+        assert_eq!(lines.position(text.find('ó').unwrap()), Position::new_at(6, 0, 5));
+        assert_eq!(lines.position(text.find('\n').unwrap()), Position::new_at(12, 0, 8));
+        assert_eq!(lines.position(13), Position::new_at(13, 1, 0));
+        assert_eq!(lines.position(text.find('ü').unwrap()), Position::new_at(18, 2, 4));
+        assert_eq!(lines.position(19), Position::new_at(18, 2, 4));
+        assert_eq!(lines.position(100), Position::new_at(text.len(), 3, 0));
+        let token = Token::new(crate::token::TokenType::ItalicBegin, 17, 18);
+        assert_eq!(lines.span(&token), (Position::new_at(17, 2, 3), Position::new_at(18, 2, 4)));
+    }
+
+    #[test]
+    fn chars_with_position() {
+        let text = "ä\nbc\n";
+        let lines = LineIndex::new(text);
+        let mut iter = CharsWithPosition::new(Position::new(), text.char_indices().peekable());
+        while iter.next().is_some() {
+            assert_eq!(*iter.position(), lines.position(iter.index()));
+        }
+    }
+}
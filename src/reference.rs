@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+
+use crate::ast::Span;
+use crate::diagnostic::Diagnostic;
+use crate::markdown;
+use crate::token::Token;
+use crate::token::TokenType;
+
+/// Struct describing a `[label]: url "title"` link definition.
+#[derive(Debug, PartialEq)]
+pub struct Definition {
+    /// The label as it is compared to references, see `normalize`.
+    pub label: String,
+    pub href: Span,
+    /// The title without its quotes or parentheses.
+    pub title: Option<Span>,
+}
+
+/// Parses `text[begin..end]` as a link definition, the url may be wrapped in `<>`
+/// and the title in `"`, `'` or parentheses.
+pub fn definition(text: &str, begin: usize, end: usize) -> Option<Definition> {
+    let line = &text[begin..end];
+    let close = line.find("]:")?;
+    let label = &line[1..close];
+    if !line.starts_with('[') || label.trim().is_empty() || label.contains(['[', ']']) {
+        return None;
+    }
+
+    let href_begin = close + 2 + whitespace(&line[close + 2..]);
+    let rest = &line[href_begin..];
+    let href = if rest.starts_with('<') {
+        let length = rest.find('>')?;
+        Span::new(begin + href_begin + 1, begin + href_begin + length)
+    } else {
+        let length = rest.find(char::is_whitespace).unwrap_or(rest.len());
+        if length == 0 {
+            return None;
+        }
+        Span::new(begin + href_begin, begin + href_begin + length)
+    };
+
+    let title_begin = href.end - begin + usize::from(rest.starts_with('<'));
+    let title_begin = title_begin + whitespace(&line[title_begin..]);
+    let rest = line[title_begin..].trim_end();
+    let title = if rest.is_empty() {
+        None
+    } else if title_begin == href.end - begin {
+        // The title has to be separated from the url.
+        return None;
+    } else {
+        let close = match rest.chars().next() {
+            Some('"') => '"',
+            Some('\'') => '\'',
+            Some('(') => ')',
+            _ => return None,
+        };
+        if rest.len() < 2 || markdown::find_unescaped(&rest[1..], close) != Some(rest.len() - 2) {
+            return None;
+        }
+        Some(Span::new(begin + title_begin + 1, begin + title_begin + rest.len() - 1))
+    };
+
+    Some(Definition {
+        label: normalize(label),
+        href,
+        title,
+    })
+}
+
+/// Returns the label the way references are matched to definitions,
+/// ignoring case and collapsing whitespace.
+pub fn normalize(label: &str) -> String {
+    label.split_whitespace().collect::<Vec<&str>>().join(" ").to_lowercase()
+}
+
+/// Returns the normalized label of a reference link.
+///
+/// `reference` is the `[label]` following the link text, a collapsed `[]` or an
+/// empty token after a shortcut reference, both use the link text as the label.
+pub fn label(text: &str, link_text: &Token, reference: &Token) -> String {
+    let label = text[reference.begin..reference.end].trim_start_matches('[').trim_end_matches(']');
+    if label.trim().is_empty() {
+        normalize(&text[link_text.begin..link_text.end])
+    } else {
+        normalize(label)
+    }
+}
+
+/// Adds a `LinkHref` token spanning the url of the definition after every `LinkReference`
+/// whose label is defined, followed by a `LinkTitle` if the definition has a title.
+/// The first definition of a label wins.
+pub fn resolve(text: &str, tokens: Vec<Token>) -> Vec<Token> {
+    let mut definitions: HashMap<String, Definition> = HashMap::new();
+    for t in tokens.iter().filter(|t| t.id == TokenType::LinkDefinition) {
+        if let Some(d) = definition(text, t.begin, t.end) {
+            definitions.entry(d.label.clone()).or_insert(d);
+        }
+    }
+    if definitions.is_empty() {
+        return tokens;
+    }
+
+    let mut output: Vec<Token> = Vec::with_capacity(tokens.len());
+    for t in tokens {
+        let d = match output.last() {
+            Some(link_text) if t.id == TokenType::LinkReference => definitions.get(&label(text, link_text, &t)),
+            _ => None,
+        };
+        output.push(t);
+        if let Some(d) = d {
+            output.push(Token::new(TokenType::LinkHref, d.href.begin, d.href.end));
+            if let Some(title) = d.title {
+                output.push(Token::new(TokenType::LinkTitle, title.begin, title.end));
+            }
+        }
+    }
+
+    output
+}
+
+/// Returns a `Diagnostic` for every `[text][label]` and `[label][]` reference without a definition.
+/// Shortcut references like `[label]` are plain text if undefined, so they aren't reported.
+pub fn undefined(text: &str, tokens: &[Token]) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    for (i, t) in tokens.iter().enumerate().skip(1) {
+        let resolved = matches!(tokens.get(i + 1), Some(n) if n.id == TokenType::LinkHref);
+        if t.id == TokenType::LinkReference && t.begin != t.end && !resolved {
+            let link_text = &tokens[i - 1];
+            diagnostics.push(Diagnostic::new(
+                format!("undefined link reference '{}'", label(text, link_text, t)),
+                Span::new(link_text.begin - 1, t.end),
+            ));
+        }
+    }
+
+    diagnostics
+}
+
+fn whitespace(text: &str) -> usize {
+    text.len() - text.trim_start().len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parts(line: &str) -> Option<(String, &str, Option<&str>)> {
+        definition(line, 0, line.len()).map(|d| (d.label, &line[d.href.begin..d.href.end], d.title.map(|t| &line[t.begin..t.end])))
+    }
+
+    #[test]
+    fn definitions() {
+        assert_eq!(parts("[Foo  Bar]: /url"), Some(("foo bar".to_string(), "/url", None)));
+        assert_eq!(parts("[a]: <my url> \"The title\"  "), Some(("a".to_string(), "my url", Some("The title"))));
+        assert_eq!(parts("[a]:/url 'single'"), Some(("a".to_string(), "/url", Some("single"))));
+        assert_eq!(parts("[a]: /url (paren)"), Some(("a".to_string(), "/url", Some("paren"))));
+        assert_eq!(parts("[a]: /url \"unclosed"), None);
+        assert_eq!(parts("[a]: /url trailing"), None);
+        assert_eq!(parts("[a]:"), None);
+        assert_eq!(parts("[]: /url"), None);
+        assert_eq!(parts("[a] /url"), None);
+    }
+}
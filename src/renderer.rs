@@ -0,0 +1,605 @@
+use crate::ast::Alignment;
+use crate::ast::Document;
+use crate::ast::Emphasis;
+use crate::ast::Highlight;
+use crate::ast::Node;
+use crate::escape::escape_attribute;
+use crate::escape::escape_code;
+use crate::escape::escape_text;
+use crate::footnote;
+use crate::toc::TocEntry;
+
+/// Trait for turning the document tree into some output format.
+///
+/// Every element has its own method which gets the already rendered `content` of its children.
+/// The default implementations generate html, so a renderer only needs to override
+/// the methods for the elements it wants to output differently.
+///
+/// Arguments other than `content` are passed as they appear in the markdown,
+/// the default implementations escape them for the context they are used in.
+pub trait Renderer {
+    fn heading(&mut self, level: usize, id: &str, content: &str) -> String {
+        heading_html(level, id, content, false)
+    }
+
+    fn paragraph(&mut self, content: &str) -> String {
+        format!("<p>{}</p>\n", content)
+    }
+
+    fn blockquote(&mut self, content: &str) -> String {
+        format!("<blockquote>\n{}</blockquote>\n", content)
+    }
+
+    fn alert(&mut self, kind: &str, title: &str, content: &str) -> String {
+        format!("<div class=\"alert alert-{}\">\n<p class=\"alert-title\">{}</p>\n{}</div>\n", escape_attribute(kind), escape_text(title), content)
+    }
+
+    fn list(&mut self, ordered: bool, start: usize, content: &str) -> String {
+        if !ordered {
+            format!("\n<ul>\n{}\n</ul>\n", content)
+        } else if start != 1 {
+            format!("\n<ol start=\"{}\">\n{}\n</ol>\n", start, content)
+        } else {
+            format!("\n<ol>\n{}\n</ol>\n", content)
+        }
+    }
+
+    fn list_item(&mut self, content: &str) -> String {
+        format!("<li>{}</li>", content)
+    }
+
+    fn table(&mut self, content: &str) -> String {
+        format!("<table>\n{}</table>\n", content)
+    }
+
+    fn row(&mut self, header: bool, content: &str) -> String {
+        format!("<tr>\n{}</tr>\n", content)
+    }
+
+    fn cell(&mut self, header: bool, alignment: Alignment, content: &str) -> String {
+        let tag = if header { "th" } else { "td" };
+        format!("<{} align=\"{}\">\n{}\n</{}>\n", tag, alignment_name(alignment), content, tag)
+    }
+
+    fn code_block(&mut self, language: &str, content: &str) -> String {
+        format!("<pre class=\"language {}\">\n{}</pre>", escape_attribute(language), content)
+    }
+
+    /// Wraps a line of a code block with numbered or highlighted lines, `number` is shown if `numbered` is true.
+    fn code_line(&mut self, number: usize, numbered: bool, highlighted: bool, content: &str) -> String {
+        let class = if highlighted { "line highlighted" } else { "line" };
+        if numbered {
+            format!("<span class=\"{}\"><span class=\"line-number\">{}</span>{}</span>\n", class, number, content)
+        } else {
+            format!("<span class=\"{}\">{}</span>\n", class, content)
+        }
+    }
+
+    fn code_span(&mut self, highlight: Highlight, text: &str) -> String {
+        match highlight_class(highlight) {
+            Some(class) => format!("<span class=\"{}\">{}</span>", class, escape_code(text)),
+            None => escape_code(text),
+        }
+    }
+
+    fn indent_block(&mut self, text: &str) -> String {
+        format!("<pre>{}</pre>", escape_code(text))
+    }
+
+    fn horizontal_rule(&mut self) -> String {
+        "<hr>\n".to_string()
+    }
+
+    /// Renders the `[TOC]` placeholder as nested lists of links to the headings.
+    fn toc(&mut self, entries: &[TocEntry]) -> String {
+        toc_html(entries, 1, 6)
+    }
+
+    /// Renders the section at the end of the document containing the footnote definitions.
+    fn footnotes(&mut self, content: &str) -> String {
+        format!("<section class=\"footnotes\">\n<ol>\n{}</ol>\n</section>\n", content)
+    }
+
+    /// Renders a footnote followed by a link back to each of its `references`.
+    fn footnote_definition(&mut self, number: usize, references: usize, content: &str) -> String {
+        let backrefs: Vec<String> = (1..=references)
+            .map(|index| format!("<a class=\"footnote-backref\" href=\"#{}\">↩</a>", footnote::reference_id(number, index)))
+            .collect();
+        let backrefs = backrefs.join(" ");
+        // The links are put into the last paragraph, so they don't end up on a line of their own.
+        match content.strip_suffix("</p>\n") {
+            Some(content) => format!("<li id=\"{}\">{} {}</p>\n</li>\n", footnote::definition_id(number), content, backrefs),
+            None => format!("<li id=\"{}\">{}{}</li>\n", footnote::definition_id(number), content, backrefs),
+        }
+    }
+
+    /// Renders a reference as the superscript number of the footnote, linking to its definition.
+    fn footnote_reference(&mut self, number: usize, index: usize) -> String {
+        format!("<sup class=\"footnote-ref\"><a href=\"#{}\" id=\"{}\">{}</a></sup>", footnote::definition_id(number), footnote::reference_id(number, index), number)
+    }
+
+    fn emphasis(&mut self, kind: Emphasis, content: &str) -> String {
+        let tag = emphasis_tag(kind);
+        format!("<{}>{}</{}>", tag, content, tag)
+    }
+
+    fn code(&mut self, text: &str) -> String {
+        format!("<code>{}</code>", escape_code(text))
+    }
+
+    fn link(&mut self, href: &str, title: Option<&str>, content: &str) -> String {
+        format!("<a href=\"{}\"{}>{}</a>", escape_attribute(href), title_attribute(title), content)
+    }
+
+    fn image(&mut self, alt: &str, src: &str, title: Option<&str>) -> String {
+        format!("<img class=\"md-img\" alt=\"{}\" src=\"{}\"{}>", escape_attribute(alt), escape_attribute(src), title_attribute(title))
+    }
+
+    fn checkbox(&mut self, checked: bool) -> String {
+        if checked {
+            "<input type=\"checkbox\" checked>".to_string()
+        } else {
+            "<input type=\"checkbox\">".to_string()
+        }
+    }
+
+    fn html(&mut self, html: &str) -> String {
+        html.to_string()
+    }
+
+    fn text(&mut self, text: &str) -> String {
+        escape_text(text)
+    }
+
+    fn soft_break(&mut self) -> String {
+        "\n".to_string()
+    }
+
+    fn line_break(&mut self) -> String {
+        "<br>\n".to_string()
+    }
+
+    fn error(&mut self, text: &str) -> String {
+        format!("<span class=\"error\">ERROR: {}</span>\n", escape_text(text))
+    }
+
+    /// Renders the whole document.
+    fn render(&mut self, document: &Document) -> String {
+        self.render_nodes(&document.children)
+    }
+
+    fn render_nodes(&mut self, nodes: &[Node]) -> String {
+        let mut output = String::new();
+        for node in nodes.iter() {
+            output.push_str(&self.render_node(node));
+        }
+
+        output
+    }
+
+    /// Renders the items of a tight list, the paragraphs directly inside an item are output without `paragraph`.
+    fn render_tight(&mut self, items: &[Node]) -> String {
+        let mut output = String::new();
+        for item in items.iter() {
+            match item {
+                Node::ListItem { children, .. } => {
+                    let mut content = String::new();
+                    for node in children.iter() {
+                        match node {
+                            Node::Paragraph { children, .. } => content.push_str(&self.render_nodes(children)),
+                            _ => content.push_str(&self.render_node(node)),
+                        }
+                    }
+                    output.push_str(&self.list_item(&content));
+                },
+                _ => output.push_str(&self.render_node(item)),
+            }
+        }
+
+        output
+    }
+
+    /// Renders the `CodeSpan`s of a code block line by line and passes every line to `code_line`.
+    /// Spans containing newlines, like multi-line comments, are split so each line is closed on its own.
+    fn render_code_lines(&mut self, spans: &[Node], numbered: bool, first_line: usize, highlighted: &[(usize, usize)]) -> String {
+        if spans.is_empty() {
+            return String::new();
+        }
+        let mut lines: Vec<String> = vec![String::new()];
+        for node in spans.iter() {
+            if let Node::CodeSpan { highlight, text, .. } = node {
+                for (i, part) in text.split('\n').enumerate() {
+                    if i > 0 {
+                        lines.push(String::new());
+                    }
+                    if !part.is_empty() {
+                        let html = self.code_span(*highlight, part);
+                        lines.last_mut().unwrap().push_str(&html);
+                    }
+                }
+            }
+        }
+        // The newline ending the last line doesn't start another one.
+        if lines.len() > 1 && lines[lines.len() - 1].is_empty() {
+            lines.pop();
+        }
+
+        let mut output = String::new();
+        for (i, content) in lines.iter().enumerate() {
+            let number = first_line + i;
+            let emphasized = highlighted.iter().any(|&(first, last)| first <= number && number <= last);
+            output.push_str(&self.code_line(number, numbered, emphasized, content));
+        }
+
+        output
+    }
+
+    /// Renders the children of the node and passes them to the method of the element.
+    fn render_node(&mut self, node: &Node) -> String {
+        match node {
+            Node::Heading { level, id, children, .. } => {
+                let content = self.render_nodes(children);
+                self.heading(*level, id, &content)
+            },
+            Node::Paragraph { children, .. } => {
+                let content = self.render_nodes(children);
+                self.paragraph(&content)
+            },
+            Node::Blockquote { children, .. } => {
+                let content = self.render_nodes(children);
+                self.blockquote(&content)
+            },
+            Node::Alert { kind, title, children, .. } => {
+                let content = self.render_nodes(children);
+                self.alert(kind, title, &content)
+            },
+            Node::List { ordered, start, tight, children, .. } => {
+                let content = if *tight { self.render_tight(children) } else { self.render_nodes(children) };
+                self.list(*ordered, *start, &content)
+            },
+            Node::ListItem { children, .. } => {
+                let content = self.render_nodes(children);
+                self.list_item(&content)
+            },
+            Node::Table { children, .. } => {
+                let content = self.render_nodes(children);
+                self.table(&content)
+            },
+            Node::Row { header, children, .. } => {
+                let content = self.render_nodes(children);
+                self.row(*header, &content)
+            },
+            Node::Cell { header, alignment, children, .. } => {
+                let content = self.render_nodes(children);
+                self.cell(*header, *alignment, &content)
+            },
+            Node::CodeBlock { language, line_numbers, first_line, highlighted_lines, children, .. } => {
+                let content = if *line_numbers || !highlighted_lines.is_empty() {
+                    self.render_code_lines(children, *line_numbers, *first_line, highlighted_lines)
+                } else {
+                    self.render_nodes(children)
+                };
+                self.code_block(language, &content)
+            },
+            Node::CodeSpan { highlight, text, .. } => self.code_span(*highlight, text),
+            Node::IndentBlock { text, .. } => self.indent_block(text),
+            Node::HorizontalRule { .. } => self.horizontal_rule(),
+            Node::Toc { entries, .. } => self.toc(entries),
+            Node::Footnotes { children, .. } => {
+                let content = self.render_nodes(children);
+                self.footnotes(&content)
+            },
+            Node::FootnoteDefinition { number, references, children, .. } => {
+                let content = self.render_nodes(children);
+                self.footnote_definition(*number, *references, &content)
+            },
+            Node::FootnoteReference { number, index, .. } => self.footnote_reference(*number, *index),
+            Node::Emphasis { kind, children, .. } => {
+                let content = self.render_nodes(children);
+                self.emphasis(*kind, &content)
+            },
+            Node::Code { text, .. } => self.code(text),
+            Node::Link { href, title, children, .. } => {
+                let content = self.render_nodes(children);
+                self.link(href, title.as_deref(), &content)
+            },
+            Node::Image { alt, src, title, .. } => self.image(alt, src, title.as_deref()),
+            Node::Checkbox { checked, .. } => self.checkbox(*checked),
+            Node::Html { html, .. } => self.html(html),
+            Node::Text { text, .. } => self.text(text),
+            Node::SoftBreak { .. } => self.soft_break(),
+            Node::LineBreak { .. } => self.line_break(),
+            Node::Error { text, .. } => self.error(text),
+        }
+    }
+}
+
+/// The built-in renderer, it uses the default html of every `Renderer` method.
+#[derive(Debug)]
+pub struct HtmlRenderer {
+    /// Appends a `<a class="anchor">` linking to the heading itself to every heading.
+    pub heading_anchors: bool,
+    /// Headings of a lower level are left out of the table of contents, their children are still listed.
+    pub toc_min_depth: usize,
+    /// Headings of a higher level are left out of the table of contents.
+    pub toc_max_depth: usize,
+}
+
+impl HtmlRenderer {
+    pub fn new() -> HtmlRenderer {
+        HtmlRenderer {
+            heading_anchors: false,
+            toc_min_depth: 1,
+            toc_max_depth: 6,
+        }
+    }
+}
+
+impl Default for HtmlRenderer {
+    fn default() -> HtmlRenderer {
+        HtmlRenderer::new()
+    }
+}
+
+impl Renderer for HtmlRenderer {
+    fn heading(&mut self, level: usize, id: &str, content: &str) -> String {
+        heading_html(level, id, content, self.heading_anchors)
+    }
+
+    fn toc(&mut self, entries: &[TocEntry]) -> String {
+        toc_html(entries, self.toc_min_depth, self.toc_max_depth)
+    }
+}
+
+fn heading_html(level: usize, id: &str, content: &str, anchor: bool) -> String {
+    let id = escape_attribute(id);
+    if anchor {
+        format!("<h{} id=\"{}\">{}<a class=\"anchor\" href=\"#{}\">#</a></h{}>\n", level, id, content, id, level)
+    } else {
+        format!("<h{} id=\"{}\">{}</h{}>\n", level, id, content, level)
+    }
+}
+
+/// Returns the table of contents, an empty string if no heading is between `min` and `max`.
+fn toc_html(entries: &[TocEntry], min: usize, max: usize) -> String {
+    let items = toc_items(entries, min, max);
+    if items.is_empty() {
+        return items;
+    }
+
+    format!("<nav class=\"toc\">\n<ul>\n{}</ul>\n</nav>\n", items)
+}
+
+fn toc_items(entries: &[TocEntry], min: usize, max: usize) -> String {
+    let mut output = String::new();
+    for e in entries.iter().filter(|e| e.level <= max) {
+        let nested = toc_items(&e.children, min, max);
+        if e.level < min {
+            output.push_str(&nested);
+            continue;
+        }
+        output.push_str(&format!("<li><a href=\"#{}\">{}</a>", escape_attribute(&e.id), escape_text(&e.text)));
+        if !nested.is_empty() {
+            output.push_str(&format!("\n<ul>\n{}</ul>\n", nested));
+        }
+        output.push_str("</li>\n");
+    }
+
+    output
+}
+
+/// Returns the ` title="..."` attribute of a link or image, nothing if it has no title.
+fn title_attribute(title: Option<&str>) -> String {
+    match title {
+        Some(title) => format!(" title=\"{}\"", escape_attribute(title)),
+        None => String::new(),
+    }
+}
+
+fn alignment_name(alignment: Alignment) -> &'static str {
+    match alignment {
+        Alignment::Left => "left",
+        Alignment::Right => "right",
+        Alignment::Center => "center",
+    }
+}
+
+fn emphasis_tag(kind: Emphasis) -> &'static str {
+    match kind {
+        Emphasis::Italic => "i",
+        Emphasis::Bold => "b",
+        Emphasis::Strike => "strike",
+        Emphasis::Underline => "u",
+    }
+}
+
+fn highlight_class(highlight: Highlight) -> Option<&'static str> {
+    match highlight {
+        Highlight::Text => None,
+        Highlight::String => Some("string"),
+        Highlight::Char => Some("char"),
+        Highlight::Digit => Some("digit"),
+        Highlight::Keyword1 => Some("keyword1"),
+        Highlight::Keyword2 => Some("keyword2"),
+        Highlight::Keyword3 => Some("keyword3"),
+        Highlight::Symbol => Some("symbol"),
+        Highlight::Function => Some("function"),
+        Highlight::Comment => Some("comment"),
+        Highlight::Escape => Some("escape"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    struct ClassRenderer;
+
+    impl Renderer for ClassRenderer {
+        fn emphasis(&mut self, kind: Emphasis, content: &str) -> String {
+            format!("<span class=\"{:?}\">{}</span>", kind, content)
+        }
+
+        fn image(&mut self, alt: &str, src: &str, title: Option<&str>) -> String {
+            format!("<img class=\"picture\" alt=\"{}\" src=\"{}\">", alt, src)
+        }
+    }
+
+    /// Renders only the text, dropping all markup.
+    struct TextRenderer;
+
+    impl Renderer for TextRenderer {
+        fn heading(&mut self, level: usize, id: &str, content: &str) -> String {
+            format!("{}\n", content)
+        }
+
+        fn emphasis(&mut self, kind: Emphasis, content: &str) -> String {
+            content.to_string()
+        }
+
+        fn paragraph(&mut self, content: &str) -> String {
+            format!("{}\n", content)
+        }
+
+        fn soft_break(&mut self) -> String {
+            " ".to_string()
+        }
+    }
+
+    #[test]
+    fn override_methods() -> Result<(), Error> {
+        let d = crate::parse("# Title\n*a* **b** ![alt](src)\n")?;
+        assert_eq!(HtmlRenderer::new().render(&d), "<h1 id=\"title\">Title</h1>\n<p><i>a</i> <b>b</b> <img class=\"md-img\" alt=\"alt\" src=\"src\"></p>\n");
+        assert_eq!(ClassRenderer.render(&d), "<h1 id=\"title\">Title</h1>\n<p><span class=\"Italic\">a</span> <span class=\"Bold\">b</span> <img class=\"picture\" alt=\"alt\" src=\"src\"></p>\n");
+        assert_eq!(TextRenderer.render(&crate::parse("# Title\n*a*\n**b**\n")?), "Title\na b\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn escaping() -> Result<(), Error> {
+        let html = |text: &str| -> Result<String, Error> { Ok(HtmlRenderer::new().render(&crate::parse(text)?)) };
+        assert_eq!(html("a < b && c > d &copy;\n")?, "<p>a &lt; b &amp;&amp; c &gt; d &copy;</p>\n");
+        assert_eq!(html("# 1 < 2\n")?, "<h1 id=\"1--2\">1 &lt; 2</h1>\n");
+        assert_eq!(html("`Vec<T> &amp;`\n")?, "<p><code>Vec&lt;T&gt; &amp;amp;</code></p>\n");
+        assert_eq!(html("[a < b](x.html?a=1&b=\"2\")\n")?, "<p><a href=\"x.html?a=1&amp;b=&quot;2&quot;\">a &lt; b</a></p>\n");
+        assert_eq!(html("![\"alt\" <x>](a'b.png)\n")?, "<p><img class=\"md-img\" alt=\"&quot;alt&quot; &lt;x&gt;\" src=\"a&#39;b.png\"></p>\n");
+        assert_eq!(html("> a < b\n")?, "<blockquote>\n<p>a &lt; b</p>\n</blockquote>\n");
+        assert_eq!(html("a\n\n* a < b\n\nc")?, "<p>a</p>\n\n<ul>\n<li>a &lt; b</li>\n</ul>\n<p>c</p>\n");
+        assert_eq!(html("a\n\n    a<b> & c\n")?, "<p>a</p>\n<pre>a&lt;b&gt; &amp; c</pre>");
+        assert_eq!(html("```\na<b\n```\n")?, "<pre class=\"language \">\na<span class=\"symbol\">&lt;</span>b<span class=\"symbol\">\n</span></pre>");
+        assert_eq!(html("[a](b '<\"c\">') ![d](e 'f&g')\n")?, "<p><a href=\"b\" title=\"&lt;&quot;c&quot;&gt;\">a</a> <img class=\"md-img\" alt=\"d\" src=\"e\" title=\"f&amp;g\"></p>\n");
+        assert_eq!(html("<https://a.b/?x&y> <me@b.co> <a b>\n")?,
+            "<p><a href=\"https://a.b/?x&amp;y\">https://a.b/?x&amp;y</a> <a href=\"mailto:me@b.co\">me@b.co</a> <a b></p>\n");
+        assert_eq!(html("\\<b>\n")?, "<p>&lt;b&gt;</p>\n");
+        assert_eq!(html("<b>bold</b>\n")?, "<p><b>bold</b></p>\n");
+
+        let table = html("a\n\n| a<b | c |\n| --- | --- |\n| d&e | f |\n\n")?;
+        assert!(table.contains(" a&lt;b "), "{}", table);
+        assert!(table.contains(" d&amp;e "), "{}", table);
+
+        Ok(())
+    }
+
+    #[test]
+    fn list_start() -> Result<(), Error> {
+        assert_eq!(HtmlRenderer::new().render(&crate::parse("a\n\n3. c\n4. d\n\n")?), "<p>a</p>\n\n<ol start=\"3\">\n<li>c</li><li>d</li>\n</ol>\n");
+        assert_eq!(HtmlRenderer::new().render(&crate::parse("a\n\n1. c\n\n")?), "<p>a</p>\n\n<ol>\n<li>c</li>\n</ol>\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn bullets() -> Result<(), Error> {
+        assert_eq!(HtmlRenderer::new().render(&crate::parse("- a\n- [x] b\n+ c\n")?),
+            "\n<ul>\n<li>a</li><li><input type=\"checkbox\" checked> b</li>\n</ul>\n\n<ul>\n<li>c</li>\n</ul>\n");
+        assert_eq!(HtmlRenderer::new().render(&crate::parse("1. a\n1) b\n")?), "\n<ol>\n<li>a</li>\n</ol>\n\n<ol>\n<li>b</li>\n</ol>\n");
+        assert_eq!(HtmlRenderer::new().render(&crate::parse("a - [ ] b\n")?), "<p>a <input type=\"checkbox\"> b</p>\n");
+        assert_eq!(HtmlRenderer::new().render(&crate::parse("* a\n- b\n+ c\n")?),
+            "\n<ul>\n<li>a</li>\n</ul>\n\n<ul>\n<li>b</li>\n</ul>\n\n<ul>\n<li>c</li>\n</ul>\n");
+        assert_eq!(HtmlRenderer::new().render(&crate::parse("+ [ ] x\n  + [x] y\n")?),
+            "\n<ul>\n<li><input type=\"checkbox\"> x\n<ul>\n<li><input type=\"checkbox\" checked> y</li>\n</ul>\n</li>\n</ul>\n");
+        assert_eq!(HtmlRenderer::new().render(&crate::parse("-a +b\n")?), "<p>-a +b</p>\n");
+        assert_eq!(HtmlRenderer::new().render(&crate::parse("---\n")?), "<hr>\n");
+        assert_eq!(HtmlRenderer::new().render(&crate::parse("a\n+ b\n")?), "<p>a</p>\n\n<ul>\n<li>b</li>\n</ul>\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn heading_ids() -> Result<(), Error> {
+        let d = crate::parse("# Usage
+## Usage {#a\"b}
+")?;
+        assert_eq!(HtmlRenderer::new().render(&d), "<h1 id=\"usage\">Usage</h1>\n<h2 id=\"a&quot;b\">Usage</h2>\n");
+        let mut renderer = HtmlRenderer { heading_anchors: true, ..HtmlRenderer::new() };
+        assert_eq!(renderer.render(&d),
+            "<h1 id=\"usage\">Usage<a class=\"anchor\" href=\"#usage\">#</a></h1>\n<h2 id=\"a&quot;b\">Usage<a class=\"anchor\" href=\"#a&quot;b\">#</a></h2>\n");
+        let d = crate::parse("# \n\n# !!!\n")?;
+        assert_eq!(renderer.render(&d),
+            "<h1 id=\"section\"><a class=\"anchor\" href=\"#section\">#</a></h1>\n<h1 id=\"section-1\">!!!<a class=\"anchor\" href=\"#section-1\">#</a></h1>\n");
+
+        Ok(())
+    }
+
+    #[test]
+    fn toc() -> Result<(), Error> {
+        let d = crate::parse("[TOC]\n\n# A\n## B & C\n### C\n## D\n")?;
+        assert_eq!(HtmlRenderer::new().render(&d).lines().take(11).collect::<Vec<&str>>(), vec![
+            "<nav class=\"toc\">",
+            "<ul>",
+            "<li><a href=\"#a\">A</a>",
+            "<ul>",
+            "<li><a href=\"#b--c\">B &amp; C</a>",
+            "<ul>",
+            "<li><a href=\"#c\">C</a></li>",
+            "</ul>",
+            "</li>",
+            "<li><a href=\"#d\">D</a></li>",
+            "</ul>",
+        ]);
+        let mut renderer = HtmlRenderer { toc_min_depth: 2, toc_max_depth: 2, ..HtmlRenderer::new() };
+        assert!(renderer.render(&d).starts_with("<nav class=\"toc\">\n<ul>\n<li><a href=\"#b--c\">B &amp; C</a></li>\n<li><a href=\"#d\">D</a></li>\n</ul>\n</nav>\n<h1"));
+        assert_eq!(HtmlRenderer::new().render(&crate::parse("[TOC]\n")?), "");
+
+        Ok(())
+    }
+
+    #[test]
+    fn code_lines() -> Result<(), Error> {
+        let d = crate::parse("```c {linenos linenostart=9} {10}\n/* a\nb */ x\n\n```\n```c {2}\nx\ny\n```\n")?;
+        assert_eq!(HtmlRenderer::new().render(&d), "<pre class=\"language c\">\n\
+            <span class=\"line\"><span class=\"line-number\">9</span><span class=\"comment\">/* a</span></span>\n\
+            <span class=\"line highlighted\"><span class=\"line-number\">10</span><span class=\"comment\">b */</span><span class=\"symbol\"> </span>x</span>\n\
+            <span class=\"line\"><span class=\"line-number\">11</span></span>\n\
+            </pre><pre class=\"language c\">\n\
+            <span class=\"line\">x</span>\n\
+            <span class=\"line highlighted\">y</span>\n\
+            </pre>");
+        let d = crate::parse("```c {linenos=true,linenostart=5}\nx\n```\n```c {linenos,2}\nx\ny\n```\n")?;
+        assert_eq!(HtmlRenderer::new().render(&d), "<pre class=\"language c\">\n\
+            <span class=\"line\"><span class=\"line-number\">5</span>x</span>\n\
+            </pre><pre class=\"language c\">\n\
+            <span class=\"line\"><span class=\"line-number\">1</span>x</span>\n\
+            <span class=\"line highlighted\"><span class=\"line-number\">2</span>y</span>\n\
+            </pre>");
+
+        Ok(())
+    }
+
+    #[test]
+    fn footnotes() -> Result<(), Error> {
+        let d = crate::parse("a[^1] b[^2] c[^1]\n\n[^2]: Two\n[^1]: One\n\n    ```\n    x\n    ```\n")?;
+        assert_eq!(HtmlRenderer::new().render(&d), "<p>a<sup class=\"footnote-ref\"><a href=\"#fn-1\" id=\"fnref-1\">1</a></sup> \
+            b<sup class=\"footnote-ref\"><a href=\"#fn-2\" id=\"fnref-2\">2</a></sup> \
+            c<sup class=\"footnote-ref\"><a href=\"#fn-1\" id=\"fnref-1-2\">1</a></sup></p>\n\
+            <section class=\"footnotes\">\n<ol>\n\
+            <li id=\"fn-1\"><p>One</p>\n<pre class=\"language \">\nx<span class=\"symbol\">\n</span></pre>\
+            <a class=\"footnote-backref\" href=\"#fnref-1\">↩</a> <a class=\"footnote-backref\" href=\"#fnref-1-2\">↩</a></li>\n\
+            <li id=\"fn-2\"><p>Two <a class=\"footnote-backref\" href=\"#fnref-2\">↩</a></p>\n</li>\n\
+            </ol>\n</section>\n");
+
+        Ok(())
+    }
+}
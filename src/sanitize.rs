@@ -0,0 +1,314 @@
+use std::mem;
+
+use crate::ast::Document;
+use crate::ast::Node;
+use crate::ast::Span;
+use crate::diagnostic::Diagnostic;
+use crate::escape::escape_attribute;
+
+/// Enum describing what happens to raw html in safe mode.
+#[derive(Debug, PartialEq, Clone)]
+pub enum HtmlPolicy {
+    /// Raw html is removed.
+    Strip,
+    /// Raw html is shown as text.
+    Escape,
+    /// Only the listed tags and attributes are kept, everything else is removed.
+    /// Names are compared case insensitively.
+    Allow {
+        tags: Vec<String>,
+        attributes: Vec<String>,
+    },
+}
+
+/// Struct that configures the safe mode used for untrusted markdown.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SafeMode {
+    pub html: HtmlPolicy,
+    /// Url schemes allowed in links, images and allowed html attributes.
+    /// Relative urls without a scheme are always allowed.
+    pub schemes: Vec<String>,
+}
+
+impl SafeMode {
+    /// Creates a `SafeMode` that escapes raw html and allows `http`, `https` and `mailto` urls.
+    pub fn new() -> SafeMode {
+        SafeMode {
+            html: HtmlPolicy::Escape,
+            schemes: vec!["http".to_string(), "https".to_string(), "mailto".to_string()],
+        }
+    }
+
+    /// Checks whether the url has no scheme or one of the allowed `schemes`.
+    pub fn is_safe_url(&self, url: &str) -> bool {
+        match url_scheme(url) {
+            Some(scheme) => self.schemes.iter().any(|s| s.eq_ignore_ascii_case(&scheme)),
+            None => true,
+        }
+    }
+}
+
+impl Default for SafeMode {
+    fn default() -> SafeMode {
+        SafeMode::new()
+    }
+}
+
+/// Removes raw html and unsafe urls from the document according to `safe`.
+///
+/// Links with an unsafe url are replaced by their text and images by their alt text.
+/// Returns a `Diagnostic` for everything that was removed or changed.
+pub fn sanitize(document: &mut Document, safe: &SafeMode) -> Vec<Diagnostic> {
+    let mut diagnostics: Vec<Diagnostic> = Vec::new();
+    document.children = sanitize_nodes(mem::take(&mut document.children), safe, &mut diagnostics);
+
+    diagnostics
+}
+
+fn sanitize_nodes(nodes: Vec<Node>, safe: &SafeMode, diagnostics: &mut Vec<Diagnostic>) -> Vec<Node> {
+    let mut output: Vec<Node> = Vec::with_capacity(nodes.len());
+    for mut node in nodes {
+        if let Some(children) = node.children_mut() {
+            *children = sanitize_nodes(mem::take(children), safe, diagnostics);
+        }
+        match node {
+            Node::Link { href, children, span, .. } if !safe.is_safe_url(&href) => {
+                diagnostics.push(Diagnostic::new(format!("removed link with unsafe url '{}'", href), span));
+                output.extend(children);
+            },
+            Node::Image { alt, src, span, .. } if !safe.is_safe_url(&src) => {
+                diagnostics.push(Diagnostic::new(format!("removed image with unsafe url '{}'", src), span));
+                if !alt.is_empty() {
+                    output.push(Node::Text { text: alt, span });
+                }
+            },
+            Node::Html { html, span } => {
+                if let Some(node) = sanitize_html(html, span, safe, diagnostics) {
+                    output.push(node);
+                }
+            },
+            _ => output.push(node),
+        }
+    }
+
+    output
+}
+
+fn sanitize_html(html: String, span: Span, safe: &SafeMode, diagnostics: &mut Vec<Diagnostic>) -> Option<Node> {
+    match &safe.html {
+        HtmlPolicy::Strip => {
+            diagnostics.push(Diagnostic::new(format!("removed html '{}'", html), span));
+            None
+        },
+        HtmlPolicy::Escape => {
+            diagnostics.push(Diagnostic::new(format!("escaped html '{}'", html), span));
+            Some(Node::Text { text: html, span })
+        },
+        HtmlPolicy::Allow { tags, attributes } => {
+            let tag = match Tag::parse(&html) {
+                Some(tag) if contains(tags, &tag.name) => tag,
+                _ => {
+                    diagnostics.push(Diagnostic::new(format!("removed html '{}'", html), span));
+                    return None;
+                },
+            };
+            let mut output = format!("<{}{}", if tag.closing { "/" } else { "" }, tag.name);
+            for (name, value) in tag.attributes.iter() {
+                if !contains(attributes, name) {
+                    diagnostics.push(Diagnostic::new(format!("removed attribute '{}' from '<{}>'", name, tag.name), span));
+                } else if is_url_attribute(name) && !safe.is_safe_url(value) {
+                    diagnostics.push(Diagnostic::new(format!("removed attribute '{}' with unsafe url '{}'", name, value), span));
+                } else {
+                    output.push_str(&format!(" {}=\"{}\"", name, escape_attribute(value)));
+                }
+            }
+            output.push_str(if tag.self_closing { " />" } else { ">" });
+
+            Some(Node::Html { html: output, span })
+        },
+    }
+}
+
+fn contains(names: &[String], name: &str) -> bool {
+    names.iter().any(|n| n.eq_ignore_ascii_case(name))
+}
+
+fn is_url_attribute(name: &str) -> bool {
+    matches!(name, "href"|"src"|"action"|"formaction"|"background"|"poster"|"cite"|"xlink:href")
+}
+
+/// Returns the lowercase scheme of the url, if it has one.
+///
+/// Whitespace and control characters are ignored the same way browsers do,
+/// so `java\tscript:` is still recognized as `javascript`.
+fn url_scheme(url: &str) -> Option<String> {
+    let url: String = url.chars().filter(|c| !c.is_whitespace() && !c.is_control()).collect();
+    let end = url.find([':', '/', '?', '#'])?;
+    if !url[end..].starts_with(':') || end == 0 {
+        return None;
+    }
+
+    Some(url[..end].to_ascii_lowercase())
+}
+
+/// Struct that represents a single parsed html tag.
+struct Tag {
+    name: String,
+    closing: bool,
+    self_closing: bool,
+    attributes: Vec<(String, String)>,
+}
+
+impl Tag {
+    /// Parses a tag like `<a href="x">`, `</b>` or `<br/>`, comments and other markup return `None`.
+    fn parse(html: &str) -> Option<Tag> {
+        let inner = html.strip_prefix('<')?.strip_suffix('>')?;
+        let (closing, inner) = match inner.strip_prefix('/') {
+            Some(inner) => (true, inner),
+            None => (false, inner),
+        };
+        let (self_closing, inner) = match inner.strip_suffix('/') {
+            Some(inner) => (true, inner),
+            None => (false, inner),
+        };
+        let name_end = inner.find(|c: char| !c.is_ascii_alphanumeric() && c != '-').unwrap_or(inner.len());
+        if name_end == 0 || !inner.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            return None;
+        }
+        let mut tag = Tag {
+            name: inner[..name_end].to_ascii_lowercase(),
+            closing,
+            self_closing,
+            attributes: Vec::new(),
+        };
+
+        let mut rest = inner[name_end..].trim_start();
+        while !rest.is_empty() {
+            let name_end = rest.find(|c: char| c.is_whitespace() || c == '=').unwrap_or(rest.len());
+            if name_end == 0 {
+                return None;
+            }
+            let name = rest[..name_end].to_ascii_lowercase();
+            rest = rest[name_end..].trim_start();
+            let mut value = String::new();
+            if let Some(after) = rest.strip_prefix('=') {
+                let after = after.trim_start();
+                let (v, remaining) = match after.chars().next() {
+                    Some(q) if q == '"' || q == '\'' => {
+                        let end = after[1..].find(q)? + 1;
+                        (&after[1..end], &after[end + 1..])
+                    },
+                    _ => {
+                        let end = after.find(char::is_whitespace).unwrap_or(after.len());
+                        (&after[..end], &after[end..])
+                    },
+                };
+                value = decode_entities(v);
+                rest = remaining.trim_start();
+            }
+            tag.attributes.push((name, value));
+        }
+
+        Some(tag)
+    }
+}
+
+/// Decodes the character references that could hide an unsafe url scheme.
+fn decode_entities(value: &str) -> String {
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(i) = rest.find('&') {
+        decoded.push_str(&rest[..i]);
+        rest = &rest[i..];
+        let end = match rest.find(';') {
+            Some(end) => end,
+            None => break,
+        };
+        let reference = &rest[1..end];
+        let c = match reference.strip_prefix('#') {
+            Some(n) => match n.strip_prefix('x').or_else(|| n.strip_prefix('X')) {
+                Some(hex) => u32::from_str_radix(hex, 16).ok().and_then(char::from_u32),
+                None => n.parse::<u32>().ok().and_then(char::from_u32),
+            },
+            None => match reference {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "colon" => Some(':'),
+                "Tab" => Some('\t'),
+                "NewLine" => Some('\n'),
+                _ => None,
+            },
+        };
+        match c {
+            Some(c) => {
+                decoded.push(c);
+                rest = &rest[end + 1..];
+            },
+            None => {
+                decoded.push('&');
+                rest = &rest[1..];
+            },
+        }
+    }
+    decoded.push_str(rest);
+
+    decoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+
+    #[test]
+    fn urls() -> Result<(), Error> {
+        let safe = SafeMode::new();
+        assert!(safe.is_safe_url("https://example.com"));
+        assert!(safe.is_safe_url("images/a:b.png"));
+        assert!(safe.is_safe_url("#anchor"));
+        assert!(!safe.is_safe_url("javascript:alert(1)"));
+        assert!(!safe.is_safe_url(" JavaScript:alert(1)"));
+        assert!(!safe.is_safe_url("java\tscript:alert(1)"));
+        assert!(!safe.is_safe_url("data:text/html,x"));
+
+        let (html, diagnostics) = crate::to_safe_html("[click](javascript:void) ![x](vbscript:y) [ok](https://a.b)\n", &safe)?;
+        assert_eq!(html, "<p>click x <a href=\"https://a.b\">ok</a></p>\n");
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics[0].span, Span::new(0, 24));
+        let (html, diagnostics) = crate::to_safe_html("<javascript:alert(1)>\n", &safe)?;
+        assert_eq!(html, "<p>javascript:alert(1)</p>\n");
+        assert_eq!(diagnostics[0].span, Span::new(0, 21));
+
+        Ok(())
+    }
+
+    #[test]
+    fn html() -> Result<(), Error> {
+        let text = "<b onclick=\"x()\">bold</b> <script>alert(1)</script>\n";
+        let (html, diagnostics) = crate::to_safe_html(text, &SafeMode::new())?;
+        assert_eq!(html, "<p>&lt;b onclick=\"x()\"&gt;bold&lt;/b&gt; &lt;script&gt;alert(1)&lt;/script&gt;</p>\n");
+        assert_eq!(diagnostics.len(), 4);
+
+        let strip = SafeMode { html: HtmlPolicy::Strip, ..SafeMode::new() };
+        assert_eq!(crate::to_safe_html(text, &strip)?.0, "<p>bold alert(1)</p>\n");
+
+        let allow = SafeMode {
+            html: HtmlPolicy::Allow {
+                tags: vec!["b".to_string(), "a".to_string(), "br".to_string()],
+                attributes: vec!["href".to_string(), "title".to_string()],
+            },
+            ..SafeMode::new()
+        };
+        let (html, diagnostics) = crate::to_safe_html(text, &allow)?;
+        assert_eq!(html, "<p><b>bold</b> alert(1)</p>\n");
+        assert_eq!(diagnostics.len(), 3);
+        let (html, diagnostics) = crate::to_safe_html("<a href='java&#115;cript:x' title=\"a&quot;b\">a</a><br/>\n", &allow)?;
+        assert_eq!(html, "<p><a title=\"a&quot;b\">a</a><br /></p>\n");
+        assert_eq!(diagnostics.len(), 1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::HashSet;
+
+/// Turns the text of a heading into an id that can be used in urls.
+///
+/// The text is lowercased, whitespace becomes `-` and everything except letters,
+/// digits, `-` and `_` is removed, which matches the anchors GitHub generates.
+/// Whitespace at either end is left out, also when it is only there after removing characters.
+pub fn slugify(text: &str) -> String {
+    let kept: String = text.chars().filter(|c| c.is_alphanumeric() || c.is_whitespace() || *c == '-' || *c == '_').collect();
+    let mut slug = String::with_capacity(kept.len());
+    for c in kept.trim().chars() {
+        if c.is_whitespace() {
+            slug.push('-');
+        } else {
+            slug.extend(c.to_lowercase());
+        }
+    }
+
+    slug
+}
+
+/// Struct that hands out unique slugs for the headings of a document.
+#[derive(Debug, Default)]
+pub struct Slugger {
+    used: HashSet<String>,
+}
+
+impl Slugger {
+    pub fn new() -> Slugger {
+        Slugger {
+            used: HashSet::new(),
+        }
+    }
+
+    /// Returns the slug of `text`, numbered like `install-1` if it was handed out before.
+    /// Text with an empty slug, like `!!!`, gets the slug `section` as an id can't be empty.
+    pub fn slug(&mut self, text: &str) -> String {
+        let base = match slugify(text) {
+            slug if slug.is_empty() => "section".to_string(),
+            slug => slug,
+        };
+        let mut slug = base.clone();
+        let mut count: usize = 0;
+        while self.used.contains(&slug) {
+            count += 1;
+            slug = format!("{}-{}", base, count);
+        }
+        self.used.insert(slug.clone());
+
+        slug
+    }
+
+    /// Marks the id as taken, so `slug` never returns it.
+    pub fn reserve(&mut self, id: &str) {
+        self.used.insert(id.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slugs() {
+        assert_eq!(slugify("Install *quickly*!"), "install-quickly");
+        assert_eq!(slugify(" The `lex` function "), "the-lex-function");
+        assert_eq!(slugify("Grüße aus München"), "grüße-aus-münchen");
+        assert_eq!(slugify("snake_case & kebab-case"), "snake_case--kebab-case");
+        assert_eq!(slugify("🚀 Launch 🚀"), "launch");
+        assert_eq!(slugify("-dash-"), "-dash-");
+
+        let mut slugger = Slugger::new();
+        slugger.reserve("usage");
+        assert_eq!(slugger.slug("Install"), "install");
+        assert_eq!(slugger.slug("Install"), "install-1");
+        assert_eq!(slugger.slug("install"), "install-2");
+        assert_eq!(slugger.slug("Usage"), "usage-1");
+        assert_eq!(slugger.slug("!!!"), "section");
+        assert_eq!(slugger.slug(""), "section-1");
+    }
+}
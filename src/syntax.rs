@@ -2,6 +2,7 @@ use std::path;
 use std::fs;
 use std::collections::HashMap;
 
+use crate::error::Error;
 use crate::markdown;
 use crate::position::Position;
 use crate::wrapper::CharsWithPosition;
@@ -30,32 +31,38 @@ impl Syntax {
         }
     }
 
-    pub fn single_open(&self) -> char {
-        self.single_line_comment.chars().next().unwrap()
+    pub fn single_open(&self) -> Option<char> {
+        self.single_line_comment.chars().next()
     }
 
-    pub fn multi_open(&self) -> char {
-        self.multi_line_comment_open.chars().next().unwrap()
+    pub fn multi_open(&self) -> Option<char> {
+        self.multi_line_comment_open.chars().next()
     }
 }
 
-pub fn load_language_file(lang: &str) -> Option<Syntax> {
-    let p = format!("syntax/{}.toml", lang.to_string());
+pub fn load_language_file(lang: &str) -> Result<Option<Syntax>, Error> {
+    let p = format!("syntax/{}.toml", lang);
     let path = path::Path::new(&p);
 
     if path.exists() {
-        let mut content = fs::read_to_string(path).expect("Couldn't load syntax file!");
+        let mut content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => return Err(Error::Syntax { path: p, message: e.to_string() }),
+        };
         if cfg!(windows) {
             content = content.replace("\r", " ");
         }
-        return Some(parse(&content));
+        return match parse(&content) {
+            Ok(syntax) => Ok(Some(syntax)),
+            Err(message) => Err(Error::Syntax { path: p, message }),
+        };
     }
 
-    None
+    Ok(None)
 }
 
-fn parse(text: &String) -> Syntax {
-    let mut iter = CharsWithPosition::new(Position::new(), text.chars().enumerate().peekable());
+fn parse(text: &str) -> Result<Syntax, String> {
+    let mut iter = CharsWithPosition::new(Position::new(), text.char_indices().peekable());
     let mut syntax = Syntax::new();
     let mut map: HashMap<String, Vec<String>> = HashMap::with_capacity(5);
 
@@ -112,51 +119,39 @@ fn parse(text: &String) -> Syntax {
     if let Some(v) = map.remove("keywords3") {
         syntax.keywords3 = v;
     } 
-    if let Some(mut v) = map.remove("single_line_comment") {
-        syntax.single_line_comment = v.pop().unwrap();
-    } else {
-        syntax.single_line_comment = String::from("_NOT_USED");
-    }
-    if let Some(mut v) = map.remove("multi_line_comment_open") {
-        syntax.multi_line_comment_open = v.pop().unwrap();
-    } else {
-        syntax.multi_line_comment_open = String::from("_NOT_USED");
-    }
-    if let Some(mut v) = map.remove("multi_line_comment_close") {
-        syntax.multi_line_comment_close = v.pop().unwrap();
-    } else {
-        syntax.multi_line_comment_close = String::from("_NOT_USED");
-    }
+    syntax.single_line_comment = comment_value(&mut map, "single_line_comment")?;
+    syntax.multi_line_comment_open = comment_value(&mut map, "multi_line_comment_open")?;
+    syntax.multi_line_comment_close = comment_value(&mut map, "multi_line_comment_close")?;
 
-    syntax
+    Ok(syntax)
+}
+
+fn comment_value(map: &mut HashMap<String, Vec<String>>, key: &str) -> Result<String, String> {
+    match map.remove(key) {
+        Some(mut v) => match v.pop() {
+            Some(value) if !value.is_empty() => Ok(value),
+            _ => Err(format!("'{}' needs a non empty value", key)),
+        },
+        None => Ok(String::from("_NOT_USED")),
+    }
 }
 
 pub fn single_comment(single_comment: &String, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, v: (usize, char)) {
     let begin = v.0;
     if single_comment.len() != 1 {
         let single = single_comment.get(1..).unwrap();
-        if markdown::match_string(single, iter) {
-            while let Some(v) = iter.next() {
-                match v.1 {
-                    '\n' => {
-                        tokens.push(Token::new(TokenType::CodeBlockSingleLineComment, begin, iter.index()));
-                        break;
-                    },
-                    _ => (),
-                }
-            }
-        } 
-    } else {
-        while let Some(v) = iter.next() {
-            match v.1 {
-                '\n' => {
-                    tokens.push(Token::new(TokenType::CodeBlockSingleLineComment, begin, iter.index()));
-                    break;
-                },
-                _ => (),
-            }
+        if !markdown::match_string(single, iter) {
+            tokens.push(Token::new_char(TokenType::CodeBlockSymbol, v));
+            return;
         }
     }
+    while let Some(v) = iter.next() {
+        if v.1 == '\n' {
+            break;
+        }
+    }
+    // A comment on the last line of a codeblock ends with the code.
+    tokens.push(Token::new(TokenType::CodeBlockSingleLineComment, begin, iter.index()));
 }
 
 pub fn multi_comment(multi_comment_open: &String, multi_comment_close: &String, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, v: (usize, char)) {
@@ -164,12 +159,15 @@ pub fn multi_comment(multi_comment_open: &String, multi_comment_close: &String,
     let multi = multi_comment_open.get(1..).unwrap();
     let multi_close = multi_comment_close.get(0..).unwrap();
     if markdown::match_string(multi, iter) {
-        while let Some(v) = iter.next() {
+        while iter.next().is_some() {
             if markdown::match_string(multi_close, iter) {
-                tokens.push(Token::new(TokenType::CodeBlockMultiLineComment, begin, iter.index()));
                 break;
             }
         }
+        // An unclosed comment ends with the code.
+        tokens.push(Token::new(TokenType::CodeBlockMultiLineComment, begin, iter.index()));
+    } else {
+        tokens.push(Token::new_char(TokenType::CodeBlockSymbol, v));
     }
 }
 
@@ -184,13 +182,18 @@ pub fn string_or_char(begin: char, end: char, token_type: TokenType, tokens: &mu
                         break;
                     }
                     else if v.1 == '\\' {
-                        tokens.push(Token::new(token_type, start, iter.last()));
-                        tokens.push(Token::new_double(TokenType::CodeBlockEscape, iter.last()));
+                        let escape_begin = iter.last();
+                        tokens.push(Token::new(token_type, start, escape_begin));
                         iter.next();
+                        tokens.push(Token::new(TokenType::CodeBlockEscape, escape_begin, iter.index()));
                         start = iter.index();
                     }
                 },
-                None => break,
+                None => {
+                    // An unclosed string ends with the code.
+                    tokens.push(Token::new(token_type, start, iter.index()));
+                    break;
+                },
             }
         }
     }
@@ -206,184 +209,84 @@ fn is_keyword(text: &str, keywords: &Vec<String>) -> bool {
     false
 }
 
-pub fn keyword(lang: &str, keywords: (&Vec<String>, &Vec<String>, &Vec<String>), text: &String, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, v: (usize, char)) {
-    let begin = v.0;
-    while let Some(v) = iter.next() {
-        if !v.1.is_alphanumeric() && v.1 != '_' {
-            if is_keyword(&text[begin..iter.last()], keywords.0) {
-                tokens.push(Token::new(TokenType::CodeBlockKeyword1, begin, iter.last()));
-                tokens.push(Token::new(TokenType::CodeBlockSymbol, iter.last(), iter.index()));
-            } else if is_keyword(&text[begin..iter.last()], keywords.1) {
-                tokens.push(Token::new(TokenType::CodeBlockKeyword2, begin, iter.last()));
-                tokens.push(Token::new(TokenType::CodeBlockSymbol, iter.last(), iter.index()));
-            } else if is_keyword(&text[begin..iter.last()], keywords.2) {
-                tokens.push(Token::new(TokenType::CodeBlockKeyword3, begin, iter.last()));
-                tokens.push(Token::new(TokenType::CodeBlockSymbol, iter.last(), iter.index()));
-            } else if v.1 == '(' {
-                tokens.push(Token::new(TokenType::CodeBlockFunction, begin, iter.last()));
-                tokens.push(Token::new(TokenType::CodeBlockSymbol, iter.last(), iter.index()));
-            } else {
-                tokens.push(Token::new(TokenType::CodeBlockText, begin, iter.last()));
-                tokens.push(Token::new(TokenType::CodeBlockSymbol, iter.last(), iter.index()));
-            }
+/// Consumes the rest of an identifier and returns its end.
+fn identifier(iter: &mut CharsWithPosition) -> usize {
+    while let Some(n) = iter.peek() {
+        if !n.1.is_alphanumeric() && n.1 != '_' {
             break;
         }
+        iter.next();
     }
+
+    iter.index()
 }
 
-pub fn highlight_language(syntax: Syntax, lang: &str, lang_end: usize, text: &String, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, c: (usize, char)) {
-    loop {
-        match iter.next() {
-            Some(v) => {
-                match v.1 {
-                    '`' => {
-                        match iter.next() {
-                            Some(v) => {
-                                match v.1 {
-                                    '`' => {
-                                        match iter.next() {
-                                            Some(v) => {
-                                                match v.1 {
-                                                    '`' => {
-                                                        match iter.peek() {
-                                                            Some(v) => match v.1 {
-                                                                '\n' => {
-                                                                    // "iter.index() - 1" Because we dont want to include the "```" in the codeblock.
-                                                                    tokens.push(Token::new(TokenType::CodeBlockEnd, lang_end, iter.index() - 1));
-                                                                    iter.next();
-                                                                    tokens.push(Token::new_single(TokenType::Newline, iter.index()));
-                                                                    break;
-                                                                },
-                                                                _ => (),
-                                                            },
-                                                            None => tokens.push(Token::new(TokenType::Text, c.0, iter.last())),
-                                                        }
-                                                    },
-                                                    _ => (),
-                                                }
-                                            },
-                                            None => {
-                                                tokens.push(Token::new(TokenType::Text, c.0, iter.last()));
-                                                break;
-                                            },
-                                        }
-                                    },
-                                    _ => (),
-                                }
-                            },
-                            None => {
-                                tokens.push(Token::new(TokenType::Text, c.0, iter.last()));
-                                break;
-                            },
+pub fn keyword(lang: &str, keywords: (&Vec<String>, &Vec<String>, &Vec<String>), text: &str, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, v: (usize, char)) {
+    let begin = v.0;
+    let end = identifier(iter);
+    let id = if is_keyword(&text[begin..end], keywords.0) {
+        TokenType::CodeBlockKeyword1
+    } else if is_keyword(&text[begin..end], keywords.1) {
+        TokenType::CodeBlockKeyword2
+    } else if is_keyword(&text[begin..end], keywords.2) {
+        TokenType::CodeBlockKeyword3
+    } else if matches!(iter.peek(), Some(n) if n.1 == '(') {
+        TokenType::CodeBlockFunction
+    } else {
+        TokenType::CodeBlockText
+    };
+    tokens.push(Token::new(id, begin, end));
+}
+
+/// Highlights the `code` of a codeblock using the syntax of its language.
+/// The tokens span byte offsets into `code`.
+pub fn highlight_language(syntax: Syntax, lang: &str, code: &str) -> Vec<Token> {
+    let mut tokens: Vec<Token> = Vec::new();
+    let mut iter = CharsWithPosition::new(Position::new(), code.char_indices().peekable());
+    while let Some(v) = iter.next() {
+        match v.1 {
+            '"' => string_or_char('"', '"', TokenType::CodeBlockString, &mut tokens, &mut iter, v),
+            '\'' => string_or_char('\'', '\'', TokenType::CodeBlockChar, &mut tokens, &mut iter, v),
+            '0'..='9' => tokens.push(Token::new_single(TokenType::CodeBlockDigit, v.0)),
+            _ => if v.1.is_alphabetic() || v.1 == '_' {
+                    keyword(lang, (&syntax.keywords1, &syntax.keywords2, &syntax.keywords3), code, &mut tokens, &mut iter, v);
+                } else if Some(v.1) == syntax.single_open() {
+                    // TODO what an awful mess
+                    if syntax.single_open() == syntax.multi_open() {
+                        match iter.peek() {
+                            Some(p) if syntax.single_open() != Some(p.1) => multi_comment(&syntax.multi_line_comment_open,
+                                &syntax.multi_line_comment_close, &mut tokens, &mut iter, v),
+                            _ => single_comment(&syntax.single_line_comment, &mut tokens, &mut iter, v),
                         }
-                    },
-                    '"' => string_or_char('"', '"', TokenType::CodeBlockString, tokens, iter, v),
-                    '\'' => string_or_char('\'', '\'', TokenType::CodeBlockChar, tokens, iter, v),
-                    '0'..='9' => tokens.push(Token::new_single(TokenType::CodeBlockDigit, v.0)),
-                    _ => if v.1.is_alphabetic() || v.1 == '_' {
-                            keyword(lang, (&syntax.keywords1, &syntax.keywords2, &syntax.keywords3), text, tokens, iter, v);
-                        } else if v.1 == syntax.single_open() {
-                            // TODO what an awful mess
-                            if syntax.single_open() == syntax.multi_open() {
-                                if let Some(p) = iter.peek() {
-                                    if syntax.single_open() != p.1 {
-                                        multi_comment(&syntax.multi_line_comment_open,
-                                            &syntax.multi_line_comment_close, tokens, iter, v);
-                                    } else {
-                                        single_comment(&syntax.single_line_comment, tokens, iter, v);
-                                    }
-                                }
-                            } else {
-                                single_comment(&syntax.single_line_comment, tokens, iter, v);
-                            }
-                        } else if v.1 == syntax.multi_open() {
-                            multi_comment(&syntax.multi_line_comment_open,
-                                                &syntax.multi_line_comment_close, tokens, iter, v);
-                        } else {
-                            tokens.push(Token::new_single(TokenType::CodeBlockSymbol, v.0));
-                    },
-                }
-            },
-            None => {
-                tokens.push(Token::new(TokenType::Text, c.0, iter.last()));
-                break;
+                    } else {
+                        single_comment(&syntax.single_line_comment, &mut tokens, &mut iter, v);
+                    }
+                } else if Some(v.1) == syntax.multi_open() {
+                    multi_comment(&syntax.multi_line_comment_open,
+                                        &syntax.multi_line_comment_close, &mut tokens, &mut iter, v);
+                } else {
+                    tokens.push(Token::new_char(TokenType::CodeBlockSymbol, v));
             },
         }
     }
+
+    tokens
 }
 
-pub fn highlight_generic(lang_end: usize, tokens: &mut Vec<Token>, iter: &mut CharsWithPosition, c: (usize, char)) {
-    loop {
-        match iter.next() {
-            Some(v) => {
-                match v.1 {
-                    '`' => {
-                        match iter.next() {
-                            Some(v) => {
-                                match v.1 {
-                                    '`' => {
-                                        match iter.next() {
-                                            Some(v) => {
-                                                match v.1 {
-                                                    '`' => {
-                                                        match iter.peek() {
-                                                            Some(v) => match v.1 {
-                                                                '\n' => {
-                                                                    // "iter.index() - 1" Because we dont want to include the "```" in the codeblock.
-                                                                    tokens.push(Token::new(TokenType::CodeBlockEnd, lang_end, iter.index() - 1));
-                                                                    iter.next();
-                                                                    tokens.push(Token::new_single(TokenType::Newline, iter.index()));
-                                                                    break;
-                                                                },
-                                                                _ => (),
-                                                            },
-                                                            None => tokens.push(Token::new(TokenType::Text, c.0, iter.last())),
-                                                        }
-                                                    },
-                                                    _ => (),
-                                                }
-                                            },
-                                            None => {
-                                                tokens.push(Token::new(TokenType::Text, c.0, iter.last()));
-                                                break;
-                                            },
-                                        }
-                                    },
-                                    _ => (),
-                                }
-                            },
-                            None => {
-                                tokens.push(Token::new(TokenType::Text, c.0, iter.last()));
-                                break;
-                            },
-                        }
-                    },
-                    '"' => string_or_char('"', '"', TokenType::CodeBlockString, tokens, iter, v),
-                    '\'' => string_or_char('\'', '\'', TokenType::CodeBlockChar, tokens, iter, v),
-                    '0'..='9' => tokens.push(Token::new_single(TokenType::CodeBlockDigit, v.0)),
-                    _ => if v.1.is_alphabetic() || v.1 == '_' {
-                            let begin = v.0;
-                            while let Some(v) = iter.next() {
-                                if !v.1.is_alphanumeric() && v.1 != '_' {
-                                    if v.1 == '(' {
-                                        tokens.push(Token::new(TokenType::CodeBlockFunction, begin, iter.last()));
-                                        tokens.push(Token::new(TokenType::CodeBlockSymbol, iter.last(), iter.index()));
-                                    } else {
-                                        tokens.push(Token::new(TokenType::CodeBlockText, begin, iter.last()));
-                                        tokens.push(Token::new(TokenType::CodeBlockSymbol, iter.last(), iter.index()));
-                                    }
-                                    break;
-                                }
-                            }
-                        } else {
-                            tokens.push(Token::new_single(TokenType::CodeBlockSymbol, v.0));
-                    },
-                }
-            },
-            None => {
-                tokens.push(Token::new(TokenType::Text, c.0, iter.last()));
-                break;
-            },
-        }
+/// Highlights the `code` of a codeblock without a syntax file for its language,
+/// which is the same as a syntax without keywords and comments.
+pub fn highlight_generic(code: &str) -> Vec<Token> {
+    highlight_language(Syntax::new(), "", code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_comment_value() {
+        assert!(parse("keywords1 = [\"fn\"]\nsingle_line_comment = \"//\"\n").is_ok());
+        assert!(parse("single_line_comment = \"\"\n").is_err());
+        assert!(parse("multi_line_comment_open = []\n").is_err());
     }
-}
\ No newline at end of file
+}
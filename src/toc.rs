@@ -0,0 +1,89 @@
+use crate::ast::Node;
+use crate::ast::Span;
+
+/// Struct describing a heading in the table of contents of a document.
+#[derive(Debug, PartialEq, Clone)]
+pub struct TocEntry {
+    pub level: usize,
+    /// The text of the heading without any markup.
+    pub text: String,
+    /// The `id` of the heading, which links can point to with `#id`.
+    pub id: String,
+    pub span: Span,
+    /// The headings following this one until the next heading of the same or a lower level.
+    pub children: Vec<TocEntry>,
+}
+
+/// Returns the headings of the nodes and of their children as a hierarchy.
+///
+/// Every heading is put below the closest preceding heading of a lower level,
+/// so skipping a level like going from `#` to `###` still nests the heading.
+pub fn table_of_contents(nodes: &[Node]) -> Vec<TocEntry> {
+    let mut headings: Vec<TocEntry> = Vec::new();
+    collect_headings(nodes, &mut headings);
+
+    let mut entries: Vec<TocEntry> = Vec::new();
+    for heading in headings {
+        insert(&mut entries, heading);
+    }
+
+    entries
+}
+
+fn collect_headings(nodes: &[Node], headings: &mut Vec<TocEntry>) {
+    for node in nodes {
+        match node {
+            Node::Heading { level, id, span, .. } => headings.push(TocEntry {
+                level: *level,
+                text: node.plain_text(),
+                id: id.clone(),
+                span: *span,
+                children: Vec::new(),
+            }),
+            _ => collect_headings(node.children(), headings),
+        }
+    }
+}
+
+fn insert(entries: &mut Vec<TocEntry>, heading: TocEntry) {
+    match entries.last_mut() {
+        Some(last) if last.level < heading.level => insert(&mut last.children, heading),
+        _ => entries.push(heading),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::lexer;
+    use crate::options::ParseOptions;
+    use crate::parser;
+
+    fn outline(entries: &[TocEntry]) -> Vec<String> {
+        let mut lines: Vec<String> = Vec::new();
+        for e in entries {
+            lines.push(format!("{} {} #{}", e.level, e.text, e.id));
+            lines.extend(outline(&e.children).into_iter().map(|l| format!("  {}", l)));
+        }
+
+        lines
+    }
+
+    #[test]
+    fn hierarchy() -> Result<(), Error> {
+        let text = "## Intro\n# Usage\n### `lex`\n## Options\ntext\n# Usage\n";
+        let d = parser::parse(text, &lexer::lex(text, &ParseOptions::new())?, &ParseOptions::new())?;
+        let entries = table_of_contents(&d.children);
+        assert_eq!(outline(&entries), vec![
+            "2 Intro #intro",
+            "1 Usage #usage",
+            "  3 lex #lex",
+            "  2 Options #options",
+            "1 Usage #usage-1",
+        ]);
+        assert_eq!(entries[1].children[1].span, Span::new(27, 37));
+
+        Ok(())
+    }
+}
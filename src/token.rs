@@ -59,6 +59,22 @@ pub enum TokenType {
     ListItemBegin,
     ListItemEnd,
     Html,
+    ParagraphBegin,
+    ParagraphEnd,
+    LineBreak,
+    SetextHeading,
+    HeadingUnderline,
+    LinkReference,
+    LinkDefinition,
+    LinkTitle,
+    ImageTitle,
+    Autolink,
+    BareUrl,
+    FootnoteReference,
+    FootnoteDefinitionBegin,
+    FootnoteDefinitionEnd,
+    BlockquoteAlert,
+    CodeBlockAttribute,
 }
 
 impl Clone for TokenType {
@@ -68,6 +84,9 @@ impl Clone for TokenType {
 }
 
 /// Struct that represents a lexer token.
+///
+/// `begin` and `end` are byte offsets into the lexed text,
+/// so `&text[token.begin..token.end]` is the content of the token.
 #[derive(Debug, PartialEq)]
 pub struct Token {
     pub id: TokenType,
@@ -85,7 +104,7 @@ impl Token {
         }
     }
 
-    /// Creates a new `Token` with the length of one char.
+    /// Creates a new `Token` with the length of one ascii char.
     pub fn new_single(id: TokenType, begin: usize) -> Token {
         Token {
             id,
@@ -94,7 +113,16 @@ impl Token {
         }
     }
 
-    /// Creates a new `Token` with the length of two chars.
+    /// Creates a new `Token` spanning the char `c` found at byte offset `c.0`.
+    pub fn new_char(id: TokenType, c: (usize, char)) -> Token {
+        Token {
+            id,
+            begin: c.0,
+            end: c.0 + c.1.len_utf8(),
+        }
+    }
+
+    /// Creates a new `Token` with the length of two ascii chars.
     pub fn new_double(id: TokenType, begin: usize) -> Token {
         Token {
             id,
@@ -4,23 +4,37 @@ use std::str;
 use crate::position;
 use crate::token;
 
+/// Iterator over the chars of a `&str` which keeps track of the current byte offset.
+///
+/// All indices are byte offsets into the text, so they can be used to slice it.
 #[derive(Debug)]
 pub struct CharsWithPosition<'a> {
     pos: position::Position,
-    iter: iter::Peekable<iter::Enumerate<str::Chars<'a>>>,
+    last: usize,
+    iter: iter::Peekable<str::CharIndices<'a>>,
 }
 
 impl<'a> CharsWithPosition<'a> {
-    pub fn new(pos: position::Position, iter: iter::Peekable<iter::Enumerate<str::Chars>>) -> CharsWithPosition {
+    pub fn new(pos: position::Position, iter: iter::Peekable<str::CharIndices<'a>>) -> CharsWithPosition<'a> {
         CharsWithPosition {
             pos,
+            last: 0,
             iter,
         }
     }
 
     pub fn next(&mut self) -> Option<(usize, char)> {
-        self.pos.increment();
-        self.iter.next()
+        match self.iter.next() {
+            Some(c) => {
+                self.last = c.0;
+                self.pos.advance(c);
+                Some(c)
+            },
+            None => {
+                self.last = self.pos.index;
+                None
+            },
+        }
     }
 
     pub fn peek(&mut self) -> Option<&(usize, char)> {
@@ -28,21 +42,42 @@ impl<'a> CharsWithPosition<'a> {
     }
 
     pub fn nth(&mut self, n: usize) -> Option<(usize, char)> {
-        self.pos.index += n + 1;
-        self.iter.nth(n)
+        for _ in 0..n {
+            self.next()?;
+        }
+        self.next()
+    }
+
+    /// Returns the line and column right after the last consumed char.
+    pub fn position(&self) -> &position::Position {
+        &self.pos
     }
 
+    /// Returns the byte offset right after the last consumed char.
     pub fn index(&self) -> usize {
         self.pos.index
     }
 
+    /// Returns the byte offset of the last consumed char.
+    /// Once the iterator is exhausted this is the length of the text.
     pub fn last(&self) -> usize {
-        self.pos.index - 1
+        self.last
     }
 }
 
 #[derive(Debug)]
-pub struct List(pub token::TokenType, pub usize);
+pub struct ListType(pub token::TokenType, pub token::TokenType);
 
+/// Struct describing the marker of a list item, a bullet or a number followed by `.` or `)`.
 #[derive(Debug)]
-pub struct ListType(pub token::TokenType, pub token::TokenType);
+pub struct ListMarker {
+    pub list_type: ListType,
+    /// The number of an ordered list item.
+    pub number: Option<usize>,
+    /// Byte length of the bullet or of the digits of the number.
+    pub length: usize,
+    /// Byte length of the whole marker, without the space following it.
+    pub width: usize,
+    /// The bullet, or the `.` or `)` following the number.
+    pub delimiter: char,
+}
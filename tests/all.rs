@@ -1,9 +1,7 @@
-use std::io;
-
 use markdown;
 
 #[test]
-fn all() -> Result<(), io::Error> {
+fn all() -> Result<(), markdown::Error> {
     let tokens: Vec<markdown::Token> = markdown::markdown_to_html("tests/all.md", "generated_html/all.html", "css/light_theme.css")?;
     let mut count: usize = 0;
     for t in tokens {
@@ -44,7 +42,7 @@ fn all() -> Result<(), io::Error> {
             _ => (),
         }
     }
-    assert!(count == 69);
+    assert!(count == 75);
 
     Ok(())
 }
\ No newline at end of file
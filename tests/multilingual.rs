@@ -0,0 +1,43 @@
+use std::fs;
+
+use markdown;
+
+#[test]
+fn multilingual() -> Result<(), markdown::Error> {
+    let text = fs::read_to_string("tests/multilingual.md")?;
+    let html = markdown::to_html(&text)?;
+    let expected = [
+        "<h1 id=\"überschrift\">Überschrift</h1>",
+        "<h2 id=\"zażółć-gęślą-jaźń\">Zażółć gęślą jaźń 🚀</h2>",
+        "<p>Grüße aus München, <i>kursiv</i>, <b>fett</b>, <strike>durchgestrichen</strike> und <u>unterstrichen</u>.</p>",
+        "<code>Größe</code>",
+        "<a href=\"https://example.com/łódź\">Łódź 🌍</a>",
+        "<img class=\"md-img\" alt=\"Bild 🖼\" src=\"bilder/straße.png\">",
+        "<blockquote>\n<p>Cytat: „Dzień dobry” 😀</p>\n</blockquote>\n",
+        "<li>Äpfel\n<ul>\n<li>Birnen 🍐</li>\n</ul>\n</li>",
+        "<li>Śliwki</li>",
+        "<li>Erster Schritt</li><li>Drugi krok</li>",
+        "<input type=\"checkbox\" checked> erledigt ✓",
+        "<input type=\"checkbox\"> offen ✗",
+        " Spalte ä ",
+        " Grün 🟢 ",
+        " żółty ",
+        "*nicht kursiv*",
+        "<span class=\"string\">\"Grüße 🦀\"</span>",
+        "<span class=\"comment\">// Kommentar über ß\n</span>",
+        "<span class=\"char\">'ż'</span>",
+        "ünbekannt<span class=\"symbol\"> </span><span class=\"symbol\">⚡</span>",
+        "<pre>eingerückt: ä &lt; ö\n    tiefer 🌲</pre>",
+        "Ende mit Emoji 🎉",
+    ];
+    for e in expected.iter() {
+        assert!(html.contains(e), "Missing '{}' in:\n{}", e, html);
+    }
+
+    let tokens = markdown::markdown_to_html("tests/multilingual.md", "generated_html/multilingual.html", "")?;
+    for t in tokens {
+        assert!(text.get(t.begin..t.end).is_some(), "Token {:?} doesn't fall on char boundaries!", t);
+    }
+
+    Ok(())
+}